@@ -1,6 +1,11 @@
+use std::{collections::HashSet, fs, path::Path, sync::OnceLock};
+
+use anyhow::Context;
 use primes::PrimeSet;
 use serde::{Deserialize, Serialize};
 
+use crate::layout::Layout;
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum CharDef {
     Normal {
@@ -164,379 +169,218 @@ impl From<&CharDef> for char {
     }
 }
 
+/// 組み込みの文字の一覧。名前とTOMLの組
+const PRESETS: [(&str, &str); 2] = [
+    ("default", include_str!("../chars/default.toml")),
+    ("extended", include_str!("../chars/extended.toml")),
+];
+
+/// レイアウトのrolesで入力するため、文字の一覧に含めてはいけない文字
+const RESERVED: [char; 1] = ['ゔ'];
+
+/// 文字の一覧ファイルにおける1文字分の定義
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CharDefinition {
+    normal: char,
+    #[serde(default)]
+    turbid: Option<char>,
+    #[serde(default)]
+    semiturbid: Option<char>,
+    #[serde(default)]
+    sulphuric: bool,
+    #[serde(default)]
+    small: Option<char>,
+}
+
+/// 文字の一覧ファイルの内容
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InventoryDefinition {
+    chars: Vec<CharDefinition>,
+}
+
+/// 評価・配置の対象にする文字の一覧。
+///
+/// 一覧はTOMLで記述する。記述例は `chars/default.toml` を参照。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "InventoryDefinition", try_from = "InventoryDefinition")]
+pub struct Inventory {
+    definitions: Vec<CharDef>,
+    /// 各文字と、それに対応する素数
+    chars: Vec<(u64, char)>,
+}
+
+impl From<Inventory> for InventoryDefinition {
+    fn from(value: Inventory) -> Self {
+        let chars = value
+            .definitions
+            .iter()
+            .filter_map(|v| match v {
+                CharDef::Normal {
+                    normal,
+                    turbid,
+                    semiturbid,
+                    sulphuric,
+                    small,
+                } => Some(CharDefinition {
+                    normal: *normal,
+                    turbid: *turbid,
+                    semiturbid: *semiturbid,
+                    sulphuric: *sulphuric,
+                    small: *small,
+                }),
+                CharDef::Turbid | CharDef::SemiTurbid => None,
+            })
+            .collect();
+
+        InventoryDefinition { chars }
+    }
+}
+
+impl TryFrom<InventoryDefinition> for Inventory {
+    type Error = anyhow::Error;
+
+    fn try_from(definition: InventoryDefinition) -> Result<Self, Self::Error> {
+        let definitions = definition
+            .chars
+            .iter()
+            .map(|v| CharDef::Normal {
+                normal: v.normal,
+                turbid: v.turbid,
+                semiturbid: v.semiturbid,
+                sulphuric: v.sulphuric,
+                small: v.small,
+            })
+            .collect::<Vec<_>>();
+
+        let mut defined = HashSet::new();
+        for c in definitions.iter().flat_map(|v| v.chars()) {
+            if RESERVED.contains(&c) {
+                anyhow::bail!("char '{}': input by the layout, so can not be defined", c);
+            }
+            if !defined.insert(c) {
+                anyhow::bail!("char '{}': defined more than once", c);
+            }
+        }
+
+        for mark in ['、', '。'] {
+            if !definitions
+                .iter()
+                .any(|v| v.normal() == mark && v.chars().len() == 1)
+            {
+                anyhow::bail!("char '{}': should be defined without any variant", mark);
+            }
+        }
+
+        let mut pset = primes::Sieve::new();
+        let chars = pset
+            .iter()
+            .skip(2)
+            .zip(definitions.iter().flat_map(|c| c.chars()))
+            .collect();
+
+        Ok(Inventory { definitions, chars })
+    }
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Inventory::parse(PRESETS[0].1).expect("builtin chars should be valid")
+    }
+}
+
+impl Inventory {
+    /// 組み込みの一覧の名前、またはファイルのパスから文字の一覧を読み込む
+    pub fn load(name_or_path: &str) -> anyhow::Result<Inventory> {
+        let text = match PRESETS.iter().find(|(name, _)| *name == name_or_path) {
+            Some((_, text)) => text.to_string(),
+            None => fs::read_to_string(Path::new(name_or_path)).with_context(|| {
+                format!(
+                    "chars {} is neither a preset ({}) nor a readable file",
+                    name_or_path,
+                    PRESETS.map(|(name, _)| name).join(", ")
+                )
+            })?,
+        };
+
+        Inventory::parse(&text).with_context(|| format!("invalid chars {}", name_or_path))
+    }
+
+    /// TOMLの文字列から文字の一覧を読み込む
+    pub fn parse(text: &str) -> anyhow::Result<Inventory> {
+        let definition: InventoryDefinition = toml::from_str(text)?;
+        Inventory::try_from(definition)
+    }
+
+    /// 文字種の定義一覧を返す
+    pub fn definitions(&self) -> &[CharDef] {
+        &self.definitions
+    }
+
+    /// `layout` のキーに、すべての文字を割り当てられるかを確認する。
+    ///
+    /// 句読点以外の文字は、文字を割り当てるキーの無シフト面かシフト面に割り当てる必要がある
+    pub fn fits(&self, layout: &Layout) -> anyhow::Result<()> {
+        let chars = self
+            .definitions
+            .iter()
+            .filter(|v| !v.is_punctuation_mark() && !v.is_reading_point())
+            .count();
+        let slots = layout.keys().iter().filter(|v| v.assignable).count() * 2;
+
+        if chars > slots {
+            anyhow::bail!(
+                "{} chars can not be assigned to {} slots of the layout",
+                chars,
+                slots
+            );
+        }
+        Ok(())
+    }
+}
+
+static CURRENT: OnceLock<Inventory> = OnceLock::new();
+
+/// プロセス全体で利用する文字の一覧を設定する。
+///
+/// 一覧は一度しか設定できないため、[current]が呼ばれる前に設定する必要がある
+pub fn install(inventory: Inventory) -> anyhow::Result<()> {
+    let installed = CURRENT.get_or_init(|| inventory.clone());
+
+    if *installed != inventory {
+        anyhow::bail!("another chars are already in use");
+    }
+    Ok(())
+}
+
+/// 現在の文字の一覧を返す。設定されていない場合は組み込みの一覧を利用する
+pub fn current() -> &'static Inventory {
+    CURRENT.get_or_init(Inventory::default)
+}
+
 /// 文字種の定義一覧を返す
 pub fn definitions() -> Vec<CharDef> {
-    CHARS.to_vec()
+    current().definitions().to_vec()
 }
 
 /// 指定したひらがなの定義を返す
 pub fn find(char: char) -> Option<CharDef> {
-    CHARS.iter().find(|v| v.normal() == char).cloned()
+    current()
+        .definitions()
+        .iter()
+        .find(|v| v.normal() == char)
+        .cloned()
 }
 
 /// すべての文字を返す
 pub fn all_chars() -> Vec<(u64, char)> {
-    let mut pset = primes::Sieve::new();
-    let chars = CHARS.to_vec().into_iter().flat_map(|c| c.chars());
-    pset.iter().skip(2).zip(chars).collect()
+    current().chars.clone()
 }
 
-/// ひらがなの一覧。評価で利用する
-const CHARS: [CharDef; 50] = [
-    CharDef::Normal {
-        normal: 'あ',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: Some('ぁ'),
-    },
-    CharDef::Normal {
-        normal: 'い',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: Some('ぃ'),
-    },
-    CharDef::Normal {
-        normal: 'う',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: Some('ぅ'),
-    },
-    CharDef::Normal {
-        normal: 'え',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: Some('ぇ'),
-    },
-    CharDef::Normal {
-        normal: 'お',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: Some('ぉ'),
-    },
-    CharDef::Normal {
-        normal: 'か',
-        turbid: Some('が'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'き',
-        turbid: Some('ぎ'),
-        semiturbid: None,
-        sulphuric: true,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'く',
-        turbid: Some('ぐ'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'け',
-        turbid: Some('げ'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'こ',
-        turbid: Some('ご'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'さ',
-        turbid: Some('ざ'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'し',
-        turbid: Some('じ'),
-        semiturbid: None,
-        sulphuric: true,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'す',
-        turbid: Some('ず'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'せ',
-        turbid: Some('ぜ'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'そ',
-        turbid: Some('ぞ'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'た',
-        turbid: Some('だ'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'ち',
-        turbid: Some('ぢ'),
-        semiturbid: None,
-        sulphuric: true,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'つ',
-        turbid: Some('づ'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'て',
-        turbid: Some('で'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'と',
-        turbid: Some('ど'),
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'な',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'に',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: true,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'ぬ',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'ね',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'の',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'は',
-        turbid: Some('ば'),
-        semiturbid: Some('ぱ'),
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'ひ',
-        turbid: Some('び'),
-        semiturbid: Some('ぴ'),
-        sulphuric: true,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'ふ',
-        turbid: Some('ぶ'),
-        semiturbid: Some('ぷ'),
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'へ',
-        turbid: Some('べ'),
-        semiturbid: Some('ぺ'),
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'ほ',
-        turbid: Some('ぼ'),
-        semiturbid: Some('ぽ'),
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'ま',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'み',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: true,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'む',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'め',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'も',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'や',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: Some('ゃ'),
-    },
-    CharDef::Normal {
-        normal: 'ゆ',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: Some('ゅ'),
-    },
-    CharDef::Normal {
-        normal: 'よ',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: Some('ょ'),
-    },
-    CharDef::Normal {
-        normal: 'ら',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'り',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: true,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'る',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'れ',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'ろ',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'わ',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'を',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'ん',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'っ',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: '、',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: '。',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-    CharDef::Normal {
-        normal: 'ー',
-        turbid: None,
-        semiturbid: None,
-        sulphuric: false,
-        small: None,
-    },
-];
-
 #[cfg(test)]
 mod tests {
+    use super::*;
 
     #[test]
     fn all_chars_always_same_order() {
@@ -553,4 +397,45 @@ mod tests {
             "all eleemnts should be same"
         )
     }
+
+    #[test]
+    fn presets_fit_builtin_layout() {
+        // arrange
+        let layout = Layout::default();
+
+        // act
+        let inventories = PRESETS
+            .iter()
+            .map(|(name, _)| Inventory::load(name))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+
+        // assert
+        assert_eq!(inventories[0], Inventory::default());
+        assert_eq!(inventories[0].definitions.len(), 50);
+        assert!(inventories.iter().all(|v| v.fits(&layout).is_ok()));
+        assert!(inventories[1].chars.iter().any(|(_, c)| *c == 'ゎ'));
+    }
+
+    #[test]
+    fn reject_invalid_chars() {
+        // arrange
+        let base = "{ normal = \"、\" }, { normal = \"。\" }";
+
+        // act
+        let duplicated = Inventory::parse(&format!(
+            "chars = [{}, {{ normal = \"か\", turbid = \"が\" }}, {{ normal = \"が\" }}]",
+            base
+        ));
+        let reserved = Inventory::parse(&format!(
+            "chars = [{}, {{ normal = \"う\", turbid = \"ゔ\" }}]",
+            base
+        ));
+        let missing = Inventory::parse("chars = [{ normal = \"か\" }]");
+
+        // assert
+        assert!(duplicated.is_err());
+        assert!(reserved.is_err());
+        assert!(missing.is_err());
+    }
 }
@@ -0,0 +1,198 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use postcard::{from_bytes, take_from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    char_def::{self, Inventory},
+    cli::CorpusArgs,
+    config::Config,
+    hand_profile,
+    keymap::Keymap,
+    layout::{self, Layout},
+    playground::PlaygroundState,
+    score::Corpus,
+};
+
+/// Rustのversionや実行環境によらず同じ値になるhash(FNV-1a)。
+///
+/// checkpointに保存し、別のプロセスで計算した値と比較するため、[std::hash::DefaultHasher]は利用しない
+#[derive(Debug, Clone, Copy)]
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Fingerprint(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// 評価に利用した入力。
+///
+/// 再開時に中断前と異なる評価で続けないよう、評価を決める値のfingerprintを保存しておく
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scoring {
+    /// 連接の一覧を決めるオプション。一致しない場合の表示に利用する
+    pub corpus_options: String,
+    /// 連接の一覧のfingerprint
+    pub corpus: u64,
+    /// 打鍵ごとの評価を決めるオプション。一致しない場合の表示に利用する
+    pub connection_options: String,
+    /// 打鍵ごとの評価の表のfingerprint
+    pub connection_score: u64,
+}
+
+impl Scoring {
+    pub fn new(args: &CorpusArgs, corpus: &Corpus) -> Self {
+        let mut corpus_options = format!("--corpus {}", args.corpus.display());
+        if args.split_unknown {
+            corpus_options.push_str(" --split-unknown");
+        }
+
+        let timing = &args.timing;
+        let mut connection_options = timing
+            .timings
+            .iter()
+            .map(|v| format!("--timing {}", v.display()))
+            .chain(
+                timing
+                    .weights
+                    .iter()
+                    .map(|v| format!("--timing-weight {}", v)),
+            )
+            .collect::<Vec<_>>();
+        connection_options.push(format!(
+            "--timing-aggregation {:?} --trim-ratio {} --hand-profile {} --rules {}",
+            timing.timing_aggregation,
+            timing.trim_ratio,
+            hand_profile::selected(),
+            args.rules
+        ));
+
+        Scoring {
+            corpus_options,
+            corpus: corpus.conjunctions_fingerprint(),
+            connection_options: connection_options.join(" "),
+            connection_score: corpus.connection_score_fingerprint(),
+        }
+    }
+
+    /// 保存した評価と `current` が同じ評価になることを確認する
+    ///
+    /// オプションの表記が異なっていても、読み込んだ内容が同じであれば同じ評価とみなす
+    pub fn ensure_same(&self, current: &Scoring) -> anyhow::Result<()> {
+        if self.corpus != current.corpus {
+            anyhow::bail!(
+                "checkpoint was created with another corpus ({}), but got {}",
+                self.corpus_options,
+                current.corpus_options
+            );
+        }
+        if self.connection_score != current.connection_score {
+            anyhow::bail!(
+                "checkpoint was created with another timing, hand profile or rules ({}), but got {}",
+                self.connection_options,
+                current.connection_options
+            );
+        }
+        Ok(())
+    }
+}
+
+/// 最適化を中断した時点の状態。
+///
+/// 再開した場合、中断しなかった場合と同じ結果になるよう、次の世代で利用する乱数のseedも含めて保存する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// 実行時のレイアウト。keymapを復元する前に設定する必要があるため、先頭に保存する
+    pub layout: Layout,
+    /// 実行時の文字の一覧。レイアウトと同様に、keymapを復元する前に設定する必要がある
+    pub chars: Inventory,
+    /// 実行時の設定
+    pub config: Config,
+    /// 実行時の評価に利用した入力
+    pub scoring: Scoring,
+    /// 世代と個体の状態
+    pub playground: PlaygroundState,
+    /// これまでで最も良かったscoreとkeymap
+    pub best: Option<(u64, Keymap)>,
+    /// 進捗の表示に利用している直近のscore
+    pub last_scores: Vec<u64>,
+    /// 次の世代で乱数を初期化するseed
+    pub rng_seed: u64,
+}
+
+impl Checkpoint {
+    /// `path` に保存する。
+    ///
+    /// 保存中に中断されても既存のcheckpointが壊れないよう、一時ファイルに書き込んでから置き換える
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp = path.with_extension("tmp");
+        let bin = to_allocvec(self)?;
+
+        {
+            let mut output = File::create(&tmp)?;
+            output.write_all(&bin)?;
+            output.sync_all()?;
+        }
+        fs::rename(&tmp, path)?;
+
+        log::info!(
+            "checkpoint saved to {} at generation {}",
+            path.display(),
+            self.playground.generation()
+        );
+        Ok(())
+    }
+
+    /// `path` から読み込む
+    ///
+    /// 保存されていたレイアウトと文字の一覧を、プロセス全体で利用するものとして設定する
+    pub fn load(path: &Path) -> anyhow::Result<Checkpoint> {
+        let mut input = File::open(path)
+            .with_context(|| format!("can not open checkpoint {}", path.display()))?;
+        let mut buf = Vec::new();
+        input.read_to_end(&mut buf)?;
+
+        let (layout, rest) = take_from_bytes::<Layout>(&buf)
+            .with_context(|| format!("invalid checkpoint {}", path.display()))?;
+        layout::install(layout).with_context(|| {
+            format!(
+                "checkpoint {} was created with another layout",
+                path.display()
+            )
+        })?;
+        let (chars, _) = take_from_bytes::<Inventory>(rest)
+            .with_context(|| format!("invalid checkpoint {}", path.display()))?;
+        char_def::install(chars).with_context(|| {
+            format!(
+                "checkpoint {} was created with another chars",
+                path.display()
+            )
+        })?;
+
+        let checkpoint = from_bytes::<Checkpoint>(&buf)
+            .with_context(|| format!("invalid checkpoint {}", path.display()))?;
+        log::info!(
+            "checkpoint loaded from {} at generation {}",
+            path.display(),
+            checkpoint.playground.generation()
+        );
+        Ok(checkpoint)
+    }
+}
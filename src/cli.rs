@@ -0,0 +1,333 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+/// 日本語入力用のキーマップを生成・評価する
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// キーの配置を定義したレイアウトファイル。指定しない場合は組み込みのQWERTYのレイアウトを利用する
+    #[arg(long, global = true)]
+    pub layout: Option<PathBuf>,
+
+    /// 評価・配置の対象にする文字の一覧。組み込みの一覧名(default, extended)か、ファイルのパスを指定する。指定しない場合はdefaultを利用する
+    #[arg(long, global = true)]
+    pub chars: Option<String>,
+
+    /// キーを押下する手・指を調整するプロファイル。組み込みのプロファイル名(standard, angled, b-right-index, avoid-pinky)か、ファイルのパスを指定する。
+    /// シフトキーなどをどちらの手で押下するかにも影響するため、評価だけでなくキーマップの出力にも利用する。指定しない場合はstandardを利用する
+    #[arg(long, global = true)]
+    pub hand_profile: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 遺伝的アルゴリズムでキーマップを最適化する
+    Optimize(OptimizeArgs),
+
+    /// 既存のキーマップを評価する
+    Evaluate(EvaluateArgs),
+
+    /// キーマップを他の形式で出力する
+    Export(ExportArgs),
+
+    /// 頻度表の内容を表示する
+    InspectTable(InspectTableArgs),
+
+    /// 複数のキーマップの評価を比較する
+    Compare(CompareArgs),
+
+    /// typing-time.html形式の打鍵時間をCSV/TSV形式に変換する
+    ConvertTiming(ConvertTimingArgs),
+
+    /// 複数の打鍵時間を集約してCSV/TSV形式で出力し、計測した人による差が大きい組み合わせを表示する
+    AggregateTiming(AggregateTimingArgs),
+
+    /// 端末上で2キー間の打鍵時間を計測する。端末の制御にtermiosを利用するため、unixでのみ利用できる
+    #[cfg(unix)]
+    MeasureTiming(MeasureTimingArgs),
+
+    /// テキストのコーパスからn-gramの出現回数を数え、`--corpus` で利用する形式で出力する
+    CountNgrams(CountNgramsArgs),
+
+    /// 複数のn-gramの出現回数を、重みを付けて合算する
+    MergeNgrams(MergeNgramsArgs),
+}
+
+/// 評価に利用する入力データ
+#[derive(Debug, Args)]
+pub struct CorpusArgs {
+    /// n-gramと出現回数をタブ区切りで記録したファイル。n-gramの長さは混在していても良い
+    #[arg(short, long)]
+    pub corpus: PathBuf,
+
+    /// 評価で利用しない文字を含むn-gramを、除外せずにその文字で分割して利用する
+    #[arg(long)]
+    pub split_unknown: bool,
+
+    #[command(flatten)]
+    pub timing: TimingArgs,
+
+    /// 連接に対するペナルティのルール。組み込みのルール名(default)か、ファイルのパスを指定する
+    #[arg(long, default_value = "default")]
+    pub rules: String,
+}
+
+/// 2キー間の打鍵時間を集約する方法
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum TimingAggregation {
+    /// 重み付き平均
+    Mean,
+    /// 重み付き中央値
+    Median,
+    /// 両端の値を除いた重み付き平均
+    TrimmedMean,
+}
+
+/// 2キー間の打鍵時間の入力
+#[derive(Debug, Args)]
+pub struct TimingArgs {
+    /// 2キー間の打鍵時間を記録したファイル。拡張子が.htmlであればtyping-time.html形式、.tsvであればTSV、それ以外はCSVとして読み込む。
+    /// 複数回指定した場合は、キーの組み合わせごとに集約して利用する
+    #[arg(short, long = "timing", default_value = "typing-time.html")]
+    pub timings: Vec<PathBuf>,
+
+    /// 打鍵時間のファイルごとの重み。`--timing` と同じ順序で指定する。指定しない場合は全て1とする
+    #[arg(long = "timing-weight")]
+    pub weights: Vec<f64>,
+
+    /// 複数の打鍵時間を集約する方法
+    #[arg(long, value_enum, default_value_t = TimingAggregation::Mean)]
+    pub timing_aggregation: TimingAggregation,
+
+    /// `trimmed-mean` で、値の小さい側と大きい側からそれぞれ除外する、重みの合計に対する割合
+    #[arg(long, default_value_t = 0.1)]
+    pub trim_ratio: f64,
+}
+
+#[derive(Debug, Args)]
+pub struct OptimizeArgs {
+    #[command(flatten)]
+    pub data: CorpusArgs,
+
+    /// 最適化の設定を記述したTOMLファイル
+    #[arg(long, conflicts_with = "resume")]
+    pub config: Option<PathBuf>,
+
+    /// 初期状態として利用する頻度表
+    #[arg(long, conflicts_with = "resume")]
+    pub input_table: Option<PathBuf>,
+
+    /// 終了時に、最も良かったキーマップをJSONで保存するファイル。実際に利用した設定も、拡張子を.config.tomlにしたファイルに保存する
+    #[arg(long, default_value = "best_keymap.json")]
+    pub output_keymap: PathBuf,
+
+    /// 定期的に、また終了時に最適化の状態を保存するファイル
+    #[arg(long, default_value = "checkpoint.bin")]
+    pub checkpoint: PathBuf,
+
+    /// 保存したcheckpointから最適化を再開する。設定はcheckpointに保存されたものを利用し、変更できるのは `--generations` と `--workers` のみとする。
+    /// コーパス・打鍵時間・手の使い方・ルールは、中断前と同じ評価になるものを指定する必要がある
+    #[arg(long)]
+    pub resume: Option<PathBuf>,
+
+    /// 終了時に頻度表を保存するファイル。実際に利用した設定も、拡張子を.config.tomlにしたファイルに保存する
+    #[arg(long, default_value = "frequency_table.bin")]
+    pub output_table: PathBuf,
+
+    /// 1世代あたりのキーマップの数。設定ファイルより優先する
+    #[arg(long, conflicts_with = "resume")]
+    pub population_size: Option<usize>,
+
+    /// 頻度表の更新に利用するキーマップの数。設定ファイルより優先する
+    #[arg(long, conflicts_with = "resume")]
+    pub tournament_size: Option<usize>,
+
+    /// 評価に利用するworker threadの数。設定ファイルより優先する
+    #[arg(long)]
+    pub workers: Option<usize>,
+
+    /// 乱数のseed。同じseed・コーパス・設定であれば同じ結果になる。設定ファイルより優先する
+    #[arg(long, conflicts_with = "resume")]
+    pub seed: Option<u64>,
+
+    /// 実行する世代数。指定しない場合はCtrl-Cで中断されるまで実行する。設定ファイルより優先する
+    #[arg(long)]
+    pub generations: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct EvaluateArgs {
+    #[command(flatten)]
+    pub data: CorpusArgs,
+
+    /// 評価するキーマップ
+    #[arg(short, long)]
+    pub keymap: PathBuf,
+
+    /// 評価値の内訳と、評価が悪い連接を表示する
+    #[arg(long)]
+    pub explain: bool,
+
+    /// `--explain` で表示する、評価が悪い連接の数
+    #[arg(long, default_value_t = 20, requires = "explain")]
+    pub worst: usize,
+}
+
+/// キーマップを出力する形式
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    /// 各面の文字を罫線の表で表したテキスト
+    Text,
+    /// Google日本語入力(Mozc)のローマ字テーブル。同時押しを順に入力する文字列で表すため、シフトとして使うキーの単打に続けて
+    /// 同時押しの相手のキーの単打を入力すると、同時押しの文字になる。同時押しとして入力する場合はkeyd/kanataを利用する
+    Mozc,
+    /// keydの設定ファイル。IMEのローマ字入力と組み合わせて利用する
+    Keyd,
+    /// kanataの設定ファイル。IMEのローマ字入力と組み合わせて利用する
+    Kanata,
+    /// EmacsのQuailパッケージ
+    Quail,
+    /// SKK(DDSKK)の `skk-rom-kana-rule-list` に追加するルール
+    Skk,
+    /// chutoroの評価ツールで読み込む、文字とキーの列のTSV。同時押しはキーを並べて表す
+    Chutoro,
+    /// すべての面を1つのキーボードに描画したSVG
+    Svg,
+    /// SVGの図と凡例を含むHTML
+    Html,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// 出力するキーマップ
+    #[arg(short, long)]
+    pub keymap: PathBuf,
+
+    /// 出力する形式
+    #[arg(short, long, value_enum, default_value_t = ExportFormat::Text)]
+    pub format: ExportFormat,
+
+    /// keyd/kanataで、同時押しとみなすキーの押下間隔(ミリ秒)
+    #[arg(long, default_value_t = 50)]
+    pub chord_timeout: u16,
+
+    /// SVG/HTMLで、キーごとの打鍵数を色で示すために利用するコーパス。
+    /// 評価で利用しない文字はn-gramの区切りとし、n-gramのすべての文字を数えるため、値は相対的な負荷となる
+    #[arg(long)]
+    pub heatmap: Option<PathBuf>,
+
+    /// 出力先。指定しない場合は標準出力に出力する
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectTableArgs {
+    /// 表示する頻度表
+    pub table: PathBuf,
+
+    /// 各キー・各レイヤーで表示する文字の数
+    #[arg(long, default_value_t = 5)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct CompareArgs {
+    #[command(flatten)]
+    pub data: CorpusArgs,
+
+    /// 比較するキーマップ
+    #[arg(required = true, num_args = 2..)]
+    pub keymaps: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertTimingArgs {
+    /// 変換するtyping-time.html形式のファイル
+    pub input: PathBuf,
+
+    /// 出力先。拡張子が.tsvであればTSV、それ以外はCSVで出力する。指定しない場合は標準出力にCSVで出力する
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// 計測できなかったことを表す1000msの打鍵時間も出力する
+    #[arg(long)]
+    pub keep_capped: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct AggregateTimingArgs {
+    #[command(flatten)]
+    pub timing: TimingArgs,
+
+    /// 出力先。拡張子が.tsvであればTSV、それ以外はCSVで出力する。指定しない場合は標準出力にCSVで出力する
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// 最大値と最小値の差が、集約した打鍵時間に対してこの比率以上になる組み合わせを表示する
+    #[arg(long, default_value_t = crate::timing::DISAGREEMENT_THRESHOLD)]
+    pub disagreement: f64,
+}
+
+#[cfg(unix)]
+#[derive(Debug, Args)]
+pub struct MeasureTimingArgs {
+    /// 計測結果を保存するファイル。拡張子が.tsvであればTSV、それ以外はCSVで保存する。既に存在する場合は、記録済みの組み合わせを飛ばして続きから計測する
+    #[arg(short, long, default_value = "timing.csv")]
+    pub output: PathBuf,
+
+    /// 計測するキー。指定しない場合は、文字を割り当てる全てのキーの組み合わせを計測する
+    #[arg(long)]
+    pub keys: Option<String>,
+
+    /// 1つの組み合わせを打鍵する回数
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+    pub trials: u16,
+}
+
+#[derive(Debug, Args)]
+pub struct CountNgramsArgs {
+    /// UTF-8のテキストファイル、またはそれを含むディレクトリ。ディレクトリは再帰的に読み込む
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// 出力先。指定しない場合は標準出力に出力する
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// 数えるn-gramの長さ。カンマ区切りで複数指定できる
+    #[arg(
+        short = 'n',
+        long = "length",
+        value_delimiter = ',',
+        default_value = "4"
+    )]
+    pub lengths: Vec<usize>,
+
+    /// 記録するn-gramの種類の上限。超えた場合は出現回数が少ないn-gramから捨てるため、メモリの使用量が制限される
+    #[arg(long, default_value_t = 10_000_000)]
+    pub max_entries: usize,
+
+    /// 出力するn-gramの最小の出現回数
+    #[arg(long, default_value_t = 1)]
+    pub min_count: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct MergeNgramsArgs {
+    /// 合算するn-gramの出現回数のファイル
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// ファイルごとの重み。ファイルと同じ順序で指定する。指定しない場合は全て1とする
+    #[arg(long = "weight")]
+    pub weights: Vec<f64>,
+
+    /// 出力先。指定しない場合は標準出力に出力する
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
@@ -0,0 +1,192 @@
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::playground::Settings;
+
+const NEIGHBOR_SEARCH_INTERVAL: u64 = 100;
+const SCORE_WINDOW: usize = 1000;
+const CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// 最適化の実行に関する設定。
+///
+/// 設定ファイルはTOMLで記述する。記述されなかった項目はデフォルト値になる。
+///
+/// ```toml
+/// [playground]
+/// tournament_size = 3
+/// keymap_size = 10
+/// workers = 24
+/// mutation_prob = 0.0
+///
+/// [optimize]
+/// neighbor_search_interval = 100
+/// score_window = 1000
+/// seed = 12345
+/// generations = 10000
+/// checkpoint_interval = 1000
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub playground: Settings,
+    pub optimize: OptimizeConfig,
+}
+
+/// 最適化のループに関する設定
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OptimizeConfig {
+    /// 近傍探索を行う世代の間隔
+    pub neighbor_search_interval: u64,
+    /// 進捗の表示で平均を求める、直近のscoreの数
+    pub score_window: usize,
+    /// 乱数のseed。指定しない場合は実行ごとにランダムに決定する
+    pub seed: Option<u64>,
+    /// 実行する世代数。指定しない場合は中断されるまで実行する
+    pub generations: Option<u64>,
+    /// checkpointを保存する世代の間隔
+    pub checkpoint_interval: u64,
+}
+
+impl Default for OptimizeConfig {
+    fn default() -> Self {
+        OptimizeConfig {
+            neighbor_search_interval: NEIGHBOR_SEARCH_INTERVAL,
+            score_window: SCORE_WINDOW,
+            seed: None,
+            generations: None,
+            checkpoint_interval: CHECKPOINT_INTERVAL,
+        }
+    }
+}
+
+impl Config {
+    /// `path` から設定を読み込む
+    pub fn load(path: &Path) -> anyhow::Result<Config> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("can not read config {}", path.display()))?;
+
+        Config::parse(&text).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    /// TOMLの文字列から設定を読み込む
+    pub fn parse(text: &str) -> anyhow::Result<Config> {
+        let config: Config = toml::from_str(text)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// 設定をTOMLとして `path` に保存する
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// 設定値が実行可能な範囲にあるかを確認する
+    ///
+    /// # Returns
+    /// 範囲外の値があった場合は、そのkeyを含むエラー
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let playground = &self.playground;
+
+        if playground.keymap_size == 0 {
+            anyhow::bail!("playground.keymap_size: must be greater than 0");
+        }
+
+        if playground.workers == 0 {
+            anyhow::bail!("playground.workers: must be greater than 0");
+        }
+
+        if playground.tournament_size == 0 || playground.tournament_size > playground.keymap_size {
+            anyhow::bail!(
+                "playground.tournament_size: must be between 1 and playground.keymap_size ({})",
+                playground.keymap_size
+            );
+        }
+
+        if !(0.0..=1.0).contains(&playground.mutation_prob) {
+            anyhow::bail!("playground.mutation_prob: must be between 0.0 and 1.0");
+        }
+
+        if self.optimize.neighbor_search_interval == 0 {
+            anyhow::bail!("optimize.neighbor_search_interval: must be greater than 0");
+        }
+
+        if self.optimize.score_window == 0 {
+            anyhow::bail!("optimize.score_window: must be greater than 0");
+        }
+
+        if self.optimize.checkpoint_interval == 0 {
+            anyhow::bail!("optimize.checkpoint_interval: must be greater than 0");
+        }
+
+        if self.optimize.generations == Some(0) {
+            anyhow::bail!("optimize.generations: must be greater than 0");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn use_default_for_missing_keys() {
+        // arrange
+
+        // act
+        let config = Config::parse("[playground]\nkeymap_size = 20\n").unwrap();
+
+        // assert
+        assert_eq!(config.playground.keymap_size, 20);
+        assert_eq!(config.playground.workers, Settings::default().workers);
+        assert_eq!(config.optimize, OptimizeConfig::default());
+    }
+
+    #[test]
+    fn report_key_of_invalid_value() {
+        // arrange
+
+        // act
+        let ret = Config::parse("[playground]\nkeymap_size = 2\ntournament_size = 3\n");
+
+        // assert
+        let message = format!("{:#}", ret.unwrap_err());
+        assert!(
+            message.contains("playground.tournament_size"),
+            "should point the key: {message}"
+        );
+    }
+
+    #[test]
+    fn reject_unknown_key() {
+        // arrange
+
+        // act
+        let ret = Config::parse("[optimize]\nneighbor_interval = 10\n");
+
+        // assert
+        let message = format!("{:#}", ret.unwrap_err());
+        assert!(
+            message.contains("neighbor_interval"),
+            "should point the key: {message}"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        // arrange
+        let config = Config::default();
+
+        // act
+        let ret = Config::parse(&toml::to_string_pretty(&config).unwrap()).unwrap();
+
+        // assert
+        assert_eq!(ret, config);
+    }
+}
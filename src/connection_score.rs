@@ -1,156 +1,179 @@
-use std::{collections::HashMap, fs::File, io::Read, path::Path};
+use std::ops::{Add, AddAssign};
+
+use crate::{
+    checkpoint::Fingerprint,
+    hand_profile::HandProfile,
+    key_seq::Stroke,
+    layout::{self, Point},
+    penalty::RuleSet,
+    timing::TwoKeyTiming,
+};
 
-use scraper::{Html, Selector};
+/// 打鍵ごとの評価を事前に計算した表。
+///
+/// 文字を入力する物理的な打鍵(シフトキーの打鍵を含む)を1打鍵ずつ評価し、その合計を連接の評価とする。
+/// 1打鍵の評価は、直前2打鍵までの文脈にのみ依存するので、(2打鍵前, 1打鍵前, 今回の打鍵)の組ごとに事前計算しておく。
+pub struct ConnectionScore {
+    /// 打鍵ごとの評価。[ConnectionScore::index]でアクセスする
+    scores: Vec<u32>,
 
-use crate::layout::{
-    linear::{self, linear_layout, linear_mapping},
-    Point,
-};
+    /// 位置から、[layout::Layout::keys]におけるindex + 1への変換表。0はキーが存在しないことを表す
+    codes: Vec<usize>,
+    /// [codes]の1行あたりの幅
+    width: usize,
+    /// レイアウトのキーの数
+    keys: usize,
+    /// indexの基数。単打とシフトキーとしての打鍵を区別するため、キーの数 * 2 + 1になる
+    radix: usize,
+    /// codeから位置への変換表
+    points: Vec<Point>,
+
+    /// scoreの内訳を再計算するための打鍵時間
+    timings: TwoKeyTiming,
+    /// scoreの内訳を再計算するための手の使い方
+    profile: HandProfile,
+    /// scoreの内訳を再計算するためのペナルティのルール
+    rules: RuleSet,
+}
 
-/// 各指が担当するキーに対する重み。
-/// http://61degc.seesaa.net/article/284288569.html
-/// 上記を左右同置に改変し、多少の調整を付加
-#[rustfmt::skip]
-static FINGER_WEIGHTS: [[u16; 10];3] = [
-    [300, 126, 105, 152,   300,   300, 152, 105, 126, 300],
-    [97,   96,  91,  90,   138,   138,  90,  91,  96,  97],
-    [157, 150, 135, 135,   170,   170, 135, 135, 150,  157],
-];
-
-/// キーを押下する手の割当。1 = 左手、2 = 右手
-static HAND_ASSIGNMENT: [[u8; 10]; 3] = [
-    [1, 1, 1, 1, 1, 2, 2, 2, 2, 2],
-    [1, 1, 1, 1, 1, 2, 2, 2, 2, 2],
-    [1, 1, 1, 1, 1, 2, 2, 2, 2, 2],
-];
-
-/// キーを押下する指の割当。 1 = 人差し指、2 = 中指、３ = 薬指、４ = 小指
-static FINGER_ASSIGNMENT: [[u8; 10]; 3] = [
-    [4, 3, 2, 1, 1, 1, 1, 2, 3, 4],
-    [4, 3, 2, 1, 1, 1, 1, 2, 3, 4],
-    [4, 3, 2, 1, 1, 1, 1, 2, 3, 4],
-];
-
-/// ２キーの連接における所要時間。
-#[derive(Debug, Clone)]
-pub struct TwoKeyTiming {
-    pub timings: HashMap<(Point, Point), u32>,
+/// 1文字を入力する打鍵を、[ConnectionScore]のcodeに変換したもの
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyStrokes {
+    codes: [u16; MAX_STROKES],
+    len: u8,
 }
 
-impl TwoKeyTiming {
-    pub fn load(path: &Path) -> anyhow::Result<TwoKeyTiming> {
-        let mut timings = HashMap::new();
-        let mut file = File::open(path).unwrap();
-        let mut buf = String::new();
-        file.read_to_string(&mut buf)?;
-        let html = Html::parse_document(&buf);
-        let matrix_selector = Selector::parse("#matrix > tbody").unwrap();
-        let tr_selector = Selector::parse("tr").unwrap();
-        let td_selector = Selector::parse("td").unwrap();
-
-        let keys = [
-            'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q',
-            'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', ';', ',', '.', '/',
-        ];
-        let mappings = linear_mapping();
-
-        // tr/tdを一個ずつ対応させていく。0または1000の場合は無視する
-        // tr/tdのそれぞれ１行目は、header行なので無視する
-        for (ridx, row) in html
-            .select(&matrix_selector)
-            .next()
-            .unwrap()
-            .select(&tr_selector)
-            .skip(1)
-            .enumerate()
-        {
-            for (cidx, col) in row.select(&td_selector).skip(1).enumerate() {
-                let txt = col.text().collect::<String>();
-
-                if txt == "0" || txt == "1000" {
-                    continue;
-                }
-                let timing = txt.parse::<u32>().expect("should be numeric");
-
-                let first = mappings.get(&keys[ridx]);
-                let second = mappings.get(&keys[cidx]);
-                match (first, second) {
-                    (Some(first), Some(second)) => {
-                        timings.insert((*first, *second), timing);
-                    }
-                    _ => continue,
-                }
-            }
-        }
+/// 1文字を入力する打鍵の最大数
+const MAX_STROKES: usize = 4;
 
-        Ok(TwoKeyTiming { timings })
+impl KeyStrokes {
+    #[inline]
+    fn codes(&self) -> &[u16] {
+        &self.codes[..self.len as usize]
     }
 }
 
-pub struct ConnectionScore {
-    /// 4連接までのscore。
-    scores: Vec<u32>,
+/// scoreの内訳
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScoreBreakdown {
+    /// 各キーを押下する指の負荷
+    pub finger_load: u64,
+    /// 2キー間の打鍵時間
+    pub timing: u64,
+    /// 同じ指での連続打鍵に対するペナルティ
+    pub same_finger: u64,
+    /// 段飛ばしに対するペナルティ
+    pub skip_row: u64,
+    /// アルペジオではない連接に対するペナルティ
+    pub non_arpeggio: u64,
+    /// 小指の連続に対するペナルティ
+    pub pinky_run: u64,
+    /// その他のルールによるペナルティ
+    pub other_rules: u64,
+    /// シフトキーとの同時押しに対するペナルティ
+    pub shift: u64,
 }
 
-// struct for evaluation
-#[derive(Debug, Clone)]
-pub struct Evaluation {
-    pub positions: Point,
-    pub shift: bool,
+impl ScoreBreakdown {
+    /// 内訳の合計を返す
+    pub fn total(&self) -> u64 {
+        self.components().iter().map(|(_, v)| v).sum()
+    }
+
+    /// 内訳の名前と値を返す
+    pub fn components(&self) -> [(&'static str, u64); 8] {
+        [
+            ("finger load", self.finger_load),
+            ("timing", self.timing),
+            ("same finger", self.same_finger),
+            ("skip row", self.skip_row),
+            ("non-arpeggio", self.non_arpeggio),
+            ("pinky run", self.pinky_run),
+            ("other rules", self.other_rules),
+            ("shift", self.shift),
+        ]
+    }
+
+    /// 内訳のそれぞれを `count` 倍したものを返す
+    pub fn times(&self, count: u64) -> ScoreBreakdown {
+        ScoreBreakdown {
+            finger_load: self.finger_load * count,
+            timing: self.timing * count,
+            same_finger: self.same_finger * count,
+            skip_row: self.skip_row * count,
+            non_arpeggio: self.non_arpeggio * count,
+            pinky_run: self.pinky_run * count,
+            other_rules: self.other_rules * count,
+            shift: self.shift * count,
+        }
+    }
 }
 
-impl Default for Evaluation {
-    fn default() -> Self {
-        Self {
-            positions: Point::new(0, 0),
-            shift: false,
+impl Add for ScoreBreakdown {
+    type Output = ScoreBreakdown;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        ScoreBreakdown {
+            finger_load: self.finger_load + rhs.finger_load,
+            timing: self.timing + rhs.timing,
+            same_finger: self.same_finger + rhs.same_finger,
+            skip_row: self.skip_row + rhs.skip_row,
+            non_arpeggio: self.non_arpeggio + rhs.non_arpeggio,
+            pinky_run: self.pinky_run + rhs.pinky_run,
+            other_rules: self.other_rules + rhs.other_rules,
+            shift: self.shift + rhs.shift,
         }
     }
 }
 
+impl AddAssign for ScoreBreakdown {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
 impl ConnectionScore {
-    pub fn new(timings: &TwoKeyTiming) -> Self {
-        let mut indices = linear_layout();
-        indices.push(linear::get_left_small_shifter());
-        indices.push(linear::get_right_small_shifter());
+    /// 現在のレイアウトにおける、すべての打鍵の組み合わせを評価する
+    ///
+    /// キーを押下する手・指と、その負荷は `profile` に、連接に対するペナルティは `rules` に従う
+    pub fn new(timings: &TwoKeyTiming, profile: &HandProfile, rules: &RuleSet) -> Self {
+        let points = layout::current()
+            .keys()
+            .iter()
+            .map(|v| v.point())
+            .collect::<Vec<_>>();
+        let width = points.iter().map(|v| v.col()).max().unwrap_or(0) + 1;
+        let height = points.iter().map(|v| v.row()).max().unwrap_or(0) + 1;
+        let mut codes = vec![0; width * height];
+        for (idx, point) in points.iter().enumerate() {
+            codes[point.row() * width + point.col()] = idx + 1;
+        }
+        let keys = points.len();
+        let radix = keys * 2 + 1;
 
         let mut this = ConnectionScore {
-            scores: vec![0; 32_usize.pow(4)],
+            scores: vec![0; radix.pow(3)],
+            codes,
+            width,
+            keys,
+            radix,
+            points,
+            timings: timings.clone(),
+            profile: profile.clone(),
+            rules: rules.clone(),
         };
 
-        for i in indices.iter().cloned() {
-            let score = this.evaluate_single_connection(&i.into());
-            let index = this.get_index(&Some(i.into()), &None, &None, &None);
-            this.scores[index] = score;
-
-            for j in indices.iter().cloned() {
-                let score = this.evaluate_two_connection(&i.into(), &j.into(), timings);
-                let index = this.get_index(&Some(i.into()), &Some(j.into()), &None, &None);
-                this.scores[index] = score;
-
-                for k in indices.iter().cloned() {
-                    let score =
-                        this.evaluate_three_connection(&i.into(), &j.into(), &k.into(), timings);
-                    let index =
-                        this.get_index(&Some(i.into()), &Some(j.into()), &Some(k.into()), &None);
-                    this.scores[index] = score;
-
-                    for l in indices.iter().cloned() {
-                        let score = this.evaluate_connection(
-                            timings,
-                            &i.into(),
-                            &j.into(),
-                            &k.into(),
-                            &l.into(),
-                        );
-                        let index = this.get_index(
-                            &Some(i.into()),
-                            &Some(j.into()),
-                            &Some(k.into()),
-                            &Some(l.into()),
-                        );
-                        this.scores[index] = score;
-                    }
+        // 2打鍵前が存在する場合、1打鍵前も必ず存在する
+        for first in 0..radix {
+            for second in (0..radix).filter(|v| first == 0 || *v != 0) {
+                for third in 1..radix {
+                    let score = this.evaluate_stroke(
+                        this.stroke_of(first),
+                        this.stroke_of(second),
+                        this.stroke_of(third).expect("should be a stroke"),
+                    );
+                    let index = this.index(first, second, third);
+                    this.scores[index] = score.total() as u32;
                 }
             }
         }
@@ -158,407 +181,206 @@ impl ConnectionScore {
         this
     }
 
-    /// キーから、評価の結果を返す
+    /// 評価の表を `fingerprint` に書き込む。
     ///
-    /// ここでの結果は、4連接自体と、シフトに対する評価の両方の合算値である。
-    pub fn evaluate(&self, sequence: &[&Evaluation]) -> u64 {
-        let mut score = 0;
+    /// 表は打鍵時間・手の使い方・ルールから計算するため、これらが評価に影響する変更をしたかを判定できる
+    pub fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        for code in self.codes.iter() {
+            fingerprint.write(&(*code as u64).to_le_bytes());
+        }
+        for score in self.scores.iter() {
+            fingerprint.write(&score.to_le_bytes());
+        }
+    }
 
-        let first: (&Point, bool) = (&sequence[0].positions, sequence[0].shift);
-        let second: (&Point, bool) = (&sequence[1].positions, sequence[1].shift);
-        let third: (&Point, bool) = (&sequence[2].positions, sequence[2].shift);
-        let fourth: (&Point, bool) = (&sequence[3].positions, sequence[3].shift);
+    /// 1文字を入力する打鍵を、codeに変換する
+    pub fn key_strokes(&self, strokes: &[Stroke]) -> KeyStrokes {
+        assert!(
+            strokes.len() <= MAX_STROKES,
+            "a character should be typed in {} strokes at most",
+            MAX_STROKES
+        );
+
+        let mut key_strokes = KeyStrokes {
+            len: strokes.len() as u8,
+            ..Default::default()
+        };
+        for (code, stroke) in key_strokes.codes.iter_mut().zip(strokes) {
+            *code = self.code_of(stroke) as u16;
+        }
 
-        score += unsafe {
-            let score = *self
-                .scores
-                .get_unchecked(self.get_index_of_evaluation(&first, &second, &third, &fourth));
+        key_strokes
+    }
 
-            let shifts: i32 = first.1 as i32 + second.1 as i32 + third.1 as i32 + fourth.1 as i32;
+    /// 文字ごとの打鍵の列から、評価の結果を返す
+    ///
+    /// 各打鍵の評価を、直前2打鍵までを文脈として合算する
+    pub fn evaluate(&self, sequence: &[&KeyStrokes]) -> u64 {
+        let (mut first, mut second) = (0, 0);
+        let mut score = 0;
 
-            (score as f32 * (3_f32).sqrt().powi(shifts)) as u64
-        };
+        for strokes in sequence {
+            for code in strokes.codes() {
+                let third = *code as usize;
+                score +=
+                    unsafe { *self.scores.get_unchecked(self.index(first, second, third)) } as u64;
+                (first, second) = (second, third);
+            }
+        }
 
         score
     }
 
-    /// 4連接の評価を行う
-    ///
-    /// 4連接の評価は、以下のscoreの合算とする。
-    /// - 先頭2連接の評価
-    /// - 先頭3連接の評価
-    /// - 今回打鍵するキーの評価
+    /// 文字ごとの打鍵の列から、評価の内訳を返す
     ///
-    /// 2連接の評価は、以下のルールに従う。
-    /// - TWO_CONNECTIONにあるcost。但し設定されていない場合は0
-    ///
-    /// 3連接の評価は、以下のルールに従う。
-    /// - THREE_CONNECTIONSにあるcost。但し設定されていない場合は、打鍵するキーの評価値の合算
-    ///
-    /// # Arguments
-    /// * `i` - 1つ目のキー
-    /// * `j` - 2つ目のキー
-    /// * `k` - 3つ目のキー
-    /// * `l` - 4つ目のキー
-    ///
-    /// # Returns
-    /// 評価値
-    fn evaluate_connection(
-        &self,
-        timings: &TwoKeyTiming,
-        i: &(usize, usize),
-        j: &(usize, usize),
-        k: &(usize, usize),
-        l: &(usize, usize),
-    ) -> u32 {
-        let score = self.evaluate_three_connection(i, j, k, timings);
-        let l: Point = Point::from(*l);
-
-        score + FINGER_WEIGHTS[l.row()][l.col()] as u32
-    }
+    /// 内訳の合計は、[ConnectionScore::evaluate]の結果と一致する。
+    pub fn explain(&self, sequence: &[&KeyStrokes]) -> ScoreBreakdown {
+        let (mut first, mut second) = (None, None);
+        let mut breakdown = ScoreBreakdown::default();
+
+        for strokes in sequence {
+            for code in strokes.codes() {
+                let third = self.stroke_of(*code as usize).expect("should be a stroke");
+                breakdown += self.evaluate_stroke(first, second, third);
+                (first, second) = (second, Some(third));
+            }
+        }
 
-    /// 3連接の評価を行う
-    ///
-    /// 3連接の評価は、以下のscoreの合算とする。
-    /// - 先頭2連接の評価
-    /// - 今回打鍵するキーの評価
-    ///
-    /// 2連接の評価は、以下のルールに従う。
-    /// - TWO_CONNECTIONにあるcost。但し設定されていない場合は0
-    ///
-    /// # Arguments
-    /// * `i` - 1つ目のキー
-    /// * `j` - 2つ目のキー
-    /// * `k` - 3つ目のキー
-    ///
-    /// # Returns
-    /// 評価値
-    fn evaluate_three_connection(
-        &self,
-        i: &(usize, usize),
-        j: &(usize, usize),
-        k: &(usize, usize),
-        timings: &TwoKeyTiming,
-    ) -> u32 {
-        let two_score = self.evaluate_two_connection(i, j, timings);
-        let two_score2 = self.evaluate_two_connection(j, k, timings);
-        let i: Point = Point::from(*i);
-        let j: Point = Point::from(*j);
-        let k: Point = Point::from(*k);
-
-        two_score
-            + two_score2
-            + self.three_conjunction_scores(&i, &j, &k)
-            + FINGER_WEIGHTS[k.row()][k.col()] as u32
+        breakdown
     }
 
-    /// 2連接の評価を行う
-    ///
-    /// 2連接の評価は、以下のscoreの合算とする。
-    /// - 先頭2連接の評価
-    /// - 今回打鍵するキーの評価
+    /// 1打鍵の評価を行う
     ///
-    /// 2連接の評価は、以下のルールに従う。
-    /// - TWO_CONNECTIONにあるcost。但し設定されていない場合は0
+    /// 1打鍵の評価は、以下のscoreの合算とする。
+    /// - 今回打鍵するキーを押下する指の負荷
+    /// - 1打鍵前からの打鍵時間と、2打鍵に対するルールのペナルティ
+    /// - 2打鍵前からの3打鍵に対するルールのペナルティ
     ///
     /// # Arguments
-    /// * `i` - 1つ目のキー
-    /// * `j` - 2つ目のキー
+    /// * `first` - 2打鍵前の打鍵
+    /// * `second` - 1打鍵前の打鍵
+    /// * `third` - 今回の打鍵
     ///
     /// # Returns
     /// 評価値
-    fn evaluate_two_connection(
+    fn evaluate_stroke(
         &self,
-        i: &(usize, usize),
-        j: &(usize, usize),
-        timings: &TwoKeyTiming,
-    ) -> u32 {
-        let i: Point = Point::from(*i);
-        let j: Point = Point::from(*j);
-
-        // 2連接の評価
-        FINGER_WEIGHTS[i.row()][i.col()] as u32
-            + FINGER_WEIGHTS[j.row()][j.col()] as u32
-            + point_score::two_conjunction_scores(&i, &j, timings)
-    }
-    /// 単一キーの評価を行う
-    ///
-    /// 単一キーの評価は、以下のscoreの合算とする。
-    /// - 今回打鍵するキーの評価
-    ///
-    /// # Arguments
-    /// * `i` - 1つ目のキー
-    ///
-    /// # Returns
-    /// 評価値
-    fn evaluate_single_connection(&self, i: &(usize, usize)) -> u32 {
-        let i: Point = Point::from(*i);
-
-        FINGER_WEIGHTS[i.row()][i.col()] as u32
-    }
-
-    /// 3連接に対する評価を行う
-    fn three_conjunction_scores(&self, first: &Point, second: &Point, third: &Point) -> u32 {
-        let rules = [
-            |first: &Point, second: &Point, third: &Point| {
-                // スキップが連続している場合はペナルティ
-                if point_score::is_skip_row(first, second)
-                    && point_score::is_skip_row(second, third)
-                {
-                    300
-                } else {
-                    0
-                }
-            },
-            |first: &Point, second: &Point, third: &Point| {
-                // 同じ指を連続して打鍵しているばあいはペナルティを与える
-                if point_score::is_same_hand_and_finger(first, second)
-                    && point_score::is_same_hand_and_finger(second, third)
-                {
-                    300
-                } else {
-                    0
-                }
-            },
-            |first: &Point, second: &Point, third: &Point| {
-                // 小指が連続する場合はペナルティを与える
-                if point_score::is_pinky(first)
-                    && point_score::is_pinky(second)
-                    && point_score::is_pinky(third)
-                {
-                    200
-                } else {
-                    0
-                }
-            },
-        ];
+        first: Option<Stroke>,
+        second: Option<Stroke>,
+        third: Stroke,
+    ) -> ScoreBreakdown {
+        let mut score = ScoreBreakdown {
+            finger_load: self.profile.assignment(&third.point).weight as u64,
+            ..Default::default()
+        };
 
-        rules
-            .iter()
-            .fold(0, |score, rule| score + rule(first, second, third))
-    }
+        if let Some(second) = second {
+            score += self.rules.evaluate(&self.profile, &[second, third]);
+            score.timing += *self
+                .timings
+                .timings
+                .get(&(second.point, third.point))
+                .unwrap_or(&0) as u64;
 
-    /// 4連接に対応する全体のindexを返す。
-    ///
-    /// 26キーあるので、これが5bitに収まる。shiftのstateはいずれかにつき一回にはなる。
-    /// 全体としてこれらがこれが追加されると3bit必要になってしまい、32bitが必要になってしまい、メモリに乗らなくなってしまう。
-    /// なので、シフトの評価自体は別途行うことにする。
-    fn get_index(
-        &self,
-        i: &Option<(usize, usize)>,
-        j: &Option<(usize, usize)>,
-        k: &Option<(usize, usize)>,
-        l: &Option<(usize, usize)>,
-    ) -> usize {
-        let bit_mask = 0b00011111;
-        // rowは0-2、colは0-9なので、全部合わせても31に収まるのでこうする。bit maskは本来なくてもいいのだが、一応追加している
-        let mut index: usize = 0;
-        if let Some((r, c)) = i {
-            index = (index << 5) | ((r * 10 + c + 1) & bit_mask)
-        } else {
-            index <<= 5
-        }
-        if let Some((r, c)) = j {
-            index = (index << 5) | ((r * 10 + c + 1) & bit_mask)
-        } else {
-            index <<= 5
-        }
-        if let Some((r, c)) = k {
-            index = (index << 5) | ((r * 10 + c + 1) & bit_mask)
-        } else {
-            index <<= 5
-        }
-        if let Some((r, c)) = l {
-            index = (index << 5) | ((r * 10 + c + 1) & bit_mask)
-        } else {
-            index <<= 5
+            if let Some(first) = first {
+                score += self.rules.evaluate(&self.profile, &[first, second, third]);
+            }
         }
 
-        index
-    }
-
-    /// 4連接に対応する全体のindexを返す。
-    ///
-    /// 26キーあるので、これが5bitに収まる。shiftのstateはいずれかにつき一回にはなる。
-    /// 全体としてこれらがこれが追加されると3bit必要になってしまい、32bitが必要になってしまい、メモリに乗らなくなってしまう。
-    /// なので、シフトの評価自体は別途行うことにする。
-    fn get_index_of_evaluation(
-        &self,
-        i: &(&Point, bool),
-        j: &(&Point, bool),
-        k: &(&Point, bool),
-        l: &(&Point, bool),
-    ) -> usize {
-        let bit_mask = 0b00011111;
-        // rowは0-2、colは0-9なので、全部合わせても31に収まるのでこうする。bit maskは本来なくてもいいのだが、一応追加している
-        let mut index: usize = 0;
-        index = (index << 5) | ((i.0.row() * 10 + i.0.col() + 1) & bit_mask);
-        index = (index << 5) | ((j.0.row() * 10 + j.0.col() + 1) & bit_mask);
-        index = (index << 5) | ((k.0.row() * 10 + k.0.col() + 1) & bit_mask);
-        index = (index << 5) | ((l.0.row() * 10 + l.0.col() + 1) & bit_mask);
-
-        index
+        score
     }
-}
-
-mod point_score {
-    use crate::layout::Point;
-
-    use super::{TwoKeyTiming, FINGER_ASSIGNMENT, HAND_ASSIGNMENT};
 
+    /// 打鍵に対応するcodeを返す
     #[inline]
-    fn is_skip_row_on_same_finger(me: &Point, other: &Point) -> bool {
-        me.row() == other.row()
-            && me.col() == other.col()
-            && (me.row() as isize - other.row() as isize).abs() == 2
-    }
+    fn code_of(&self, stroke: &Stroke) -> usize {
+        let code = self.codes[stroke.point.row() * self.width + stroke.point.col()];
 
-    /// 異指で段をスキップしているか
-    #[inline]
-    pub fn is_skip_row(me: &Point, other: &Point) -> bool {
-        let hand_self = HAND_ASSIGNMENT[me.row()][me.col()];
-        let hand_other = HAND_ASSIGNMENT[other.row()][other.col()];
-        let finger_self = FINGER_ASSIGNMENT[me.row()][me.col()];
-        let finger_other = FINGER_ASSIGNMENT[other.row()][other.col()];
-
-        hand_self == hand_other
-            && finger_self != finger_other
-            && (me.row() as isize - other.row() as isize).abs() == 2
+        if stroke.shifter {
+            code + self.keys
+        } else {
+            code
+        }
     }
 
-    /// 同じ指で異なる行、異なる列を入力しているか
-    #[inline]
-    pub fn is_same_finger_dance(me: &Point, other: &Point) -> bool {
-        let hand_self = HAND_ASSIGNMENT[me.row()][me.col()];
-        let hand_other = HAND_ASSIGNMENT[other.row()][other.col()];
-        let finger_self = FINGER_ASSIGNMENT[me.row()][me.col()];
-        let finger_other = FINGER_ASSIGNMENT[other.row()][other.col()];
-
-        hand_self == hand_other
-            && finger_self == finger_other
-            && me.row() != other.row()
-            && me.col() != other.col()
+    /// codeに対応する打鍵を返す。0の場合は打鍵が存在しない
+    fn stroke_of(&self, code: usize) -> Option<Stroke> {
+        match code {
+            0 => None,
+            v if v <= self.keys => Some(Stroke::tap(self.points[v - 1])),
+            v => Some(Stroke::shifter(self.points[v - self.keys - 1])),
+        }
     }
 
+    /// 3打鍵のcodeに対応する全体のindexを返す。
     ///
+    /// 打鍵が存在しない部分は0とする
     #[inline]
-    pub fn is_arpeggio(me: &Point, other: &Point) -> bool {
-        let hand_self = HAND_ASSIGNMENT[me.row()][me.col()];
-        let hand_other = HAND_ASSIGNMENT[other.row()][other.col()];
-
-        let alpeggios = [
-            // 左手の人差し指と中指
-            (Point::new(0, 2), Point::new(1, 3)),
-            (Point::new(1, 2), Point::new(2, 3)),
-            // 左手の人差し指と小指
-            (Point::new(1, 0), Point::new(1, 3)),
-            // 左手の人差し指と薬指
-            (Point::new(0, 1), Point::new(1, 3)),
-            // 左手の中指と薬指
-            (Point::new(0, 2), Point::new(0, 1)),
-            (Point::new(1, 2), Point::new(1, 1)),
-            // 左手の中指と小指
-            (Point::new(0, 2), Point::new(1, 0)),
-            (Point::new(1, 2), Point::new(2, 0)),
-            // 左手の小指と薬指
-            (Point::new(1, 0), Point::new(0, 1)),
-            (Point::new(2, 0), Point::new(1, 1)),
-            // 右手の人差し指と中指
-            (Point::new(1, 6), Point::new(0, 7)),
-            (Point::new(2, 6), Point::new(1, 7)),
-            // 右手の人差し指と薬指
-            (Point::new(0, 8), Point::new(1, 6)),
-            // 右手の人差し指と小指
-            (Point::new(1, 9), Point::new(1, 6)),
-            // 右手の中指と薬指
-            (Point::new(0, 7), Point::new(0, 8)),
-            (Point::new(1, 7), Point::new(1, 8)),
-            // 右手の中指と小指
-            (Point::new(0, 7), Point::new(1, 9)),
-            (Point::new(1, 7), Point::new(2, 9)),
-            // 右手の小指と薬指
-            (Point::new(1, 9), Point::new(0, 8)),
-            (Point::new(2, 9), Point::new(1, 8)),
-        ];
-
-        hand_self == hand_other
-            && alpeggios
-                .iter()
-                .find(|v| **v == (*me, *other) || **v == (*other, *me))
-                .is_some()
+    fn index(&self, first: usize, second: usize, third: usize) -> usize {
+        (first * self.radix + second) * self.radix + third
     }
+}
 
-    /// 同じ手で押下しているかどうか
-    #[inline]
-    pub fn is_same_hand(me: &Point, other: &Point) -> bool {
-        HAND_ASSIGNMENT[me.row()][me.col()] == HAND_ASSIGNMENT[other.row()][other.col()]
-    }
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
 
-    #[inline]
-    pub fn is_pinky(me: &Point) -> bool {
-        FINGER_ASSIGNMENT[me.row()][me.col()] == 4
-    }
+    use super::*;
 
-    /// 同一の手、かつ同一の指かどうか
-    #[inline]
-    pub fn is_same_hand_and_finger(me: &Point, other: &Point) -> bool {
-        let finger_self = FINGER_ASSIGNMENT[me.row()][me.col()];
-        let finger_other = FINGER_ASSIGNMENT[other.row()][other.col()];
-        let hand_self = HAND_ASSIGNMENT[me.row()][me.col()];
-        let hand_other = HAND_ASSIGNMENT[other.row()][other.col()];
-
-        finger_self == finger_other && hand_self == hand_other
+    #[test]
+    fn explain_sums_up_to_evaluation() {
+        // arrange
+        let mut timings = HashMap::new();
+        timings.insert((Point::new(1, 7), Point::new(2, 1)), 120);
+        let scores = ConnectionScore::new(
+            &TwoKeyTiming { timings },
+            &HandProfile::standard(layout::current()),
+            &RuleSet::builtin(layout::current()),
+        );
+        let sequence = [
+            vec![Stroke::tap(Point::new(0, 1))],
+            vec![
+                Stroke::shifter(Point::new(1, 7)),
+                Stroke::tap(Point::new(2, 1)),
+            ],
+            vec![Stroke::tap(Point::new(0, 2))],
+            vec![Stroke::tap(Point::new(1, 0))],
+        ]
+        .iter()
+        .map(|v| scores.key_strokes(v))
+        .collect::<Vec<_>>();
+        let sequence = sequence.iter().collect::<Vec<_>>();
+
+        // act
+        let breakdown = scores.explain(&sequence);
+
+        // assert
+        assert_eq!(breakdown.total(), scores.evaluate(&sequence));
+        assert_eq!(breakdown.timing, 120, "should count timing from shifter");
+        assert!(breakdown.skip_row > 0, "should count skip row");
     }
 
-    /// 2連接に対する評価を実施する
-    pub fn two_conjunction_scores(me: &Point, other: &Point, timings: &TwoKeyTiming) -> u32 {
-        let rules = [
-            |first: &Point, second: &Point| {
-                // 同じ指で行をスキップしている場合はペナルティを与える
-                if is_skip_row_on_same_finger(first, second) {
-                    150
-                } else {
-                    0
-                }
-            },
-            |first: &Point, second: &Point| {
-                // 同じ指で異段異列の場合はペナルティを与える
-                if is_same_finger_dance(first, second) {
-                    200
-                } else {
-                    0
-                }
-            },
-            |first: &Point, second: &Point| {
-                // 同じ指で連続して押下している場合はペナルティを与える
-                if is_same_hand_and_finger(first, second) {
-                    150
-                } else {
-                    0
-                }
-            },
-            |first: &Point, second: &Point| {
-                // 段飛ばしをしている場合はペナルティを与える
-                if is_skip_row(first, second) {
-                    100
-                } else {
-                    0
-                }
+    #[test]
+    fn shifter_on_the_same_hand_costs_more() {
+        // arrange
+        let scores = ConnectionScore::new(
+            &TwoKeyTiming {
+                timings: HashMap::new(),
             },
-            |first: &Point, second: &Point| {
-                // first->secondが押下しやすいアルペジオではない場合はペナルティを与える
-                if !is_arpeggio(first, second) {
-                    50
-                } else {
-                    0
-                }
-            },
-        ];
+            &HandProfile::standard(layout::current()),
+            &RuleSet::builtin(layout::current()),
+        );
+        let strokes = |shifter: Point| {
+            scores.key_strokes(&[Stroke::shifter(shifter), Stroke::tap(Point::new(1, 0))])
+        };
+        // 右手のkと、左手のsをシフトキーとして、左手のaを押下する
+        let (alternate, same) = (strokes(Point::new(1, 7)), strokes(Point::new(1, 1)));
 
-        let special_case_score = rules
-            .iter()
-            .fold(0_u32, |score, rule| score + rule(me, other));
+        // act
+        let alternate = scores.explain(&[&alternate]);
+        let same = scores.explain(&[&same]);
 
-        special_case_score + timings.timings.get(&(*me, *other)).unwrap_or(&0)
+        // assert
+        assert_eq!(alternate.shift, 0);
+        assert!(same.shift > 0, "should count same hand shift");
     }
 }
@@ -0,0 +1,10 @@
+/// keyd/kanataの同時押しの設定
+pub mod chord;
+/// chutoroの評価ツール向けの配列定義
+pub mod chutoro;
+/// キーマップの図
+pub mod diagram;
+/// EmacsのQuailパッケージとSKKのローマ字かな変換ルール
+pub mod emacs;
+/// Google日本語入力(Mozc)のローマ字テーブル
+pub mod mozc;
@@ -0,0 +1,343 @@
+use crate::{
+    keymap::Keymap,
+    layout::{self, linear},
+};
+
+/// 文字と、IMEのローマ字入力でその文字を入力する文字列の組
+const ROMAJI: [(char, &str); 94] = [
+    ('あ', "a"),
+    ('い', "i"),
+    ('う', "u"),
+    ('え', "e"),
+    ('お', "o"),
+    ('ぁ', "xa"),
+    ('ぃ', "xi"),
+    ('ぅ', "xu"),
+    ('ぇ', "xe"),
+    ('ぉ', "xo"),
+    ('か', "ka"),
+    ('き', "ki"),
+    ('く', "ku"),
+    ('け', "ke"),
+    ('こ', "ko"),
+    ('が', "ga"),
+    ('ぎ', "gi"),
+    ('ぐ', "gu"),
+    ('げ', "ge"),
+    ('ご', "go"),
+    ('さ', "sa"),
+    ('し', "si"),
+    ('す', "su"),
+    ('せ', "se"),
+    ('そ', "so"),
+    ('ざ', "za"),
+    ('じ', "zi"),
+    ('ず', "zu"),
+    ('ぜ', "ze"),
+    ('ぞ', "zo"),
+    ('た', "ta"),
+    ('ち', "ti"),
+    ('つ', "tu"),
+    ('て', "te"),
+    ('と', "to"),
+    ('だ', "da"),
+    ('ぢ', "di"),
+    ('づ', "du"),
+    ('で', "de"),
+    ('ど', "do"),
+    ('っ', "xtu"),
+    ('な', "na"),
+    ('に', "ni"),
+    ('ぬ', "nu"),
+    ('ね', "ne"),
+    ('の', "no"),
+    ('は', "ha"),
+    ('ひ', "hi"),
+    ('ふ', "hu"),
+    ('へ', "he"),
+    ('ほ', "ho"),
+    ('ば', "ba"),
+    ('び', "bi"),
+    ('ぶ', "bu"),
+    ('べ', "be"),
+    ('ぼ', "bo"),
+    ('ぱ', "pa"),
+    ('ぴ', "pi"),
+    ('ぷ', "pu"),
+    ('ぺ', "pe"),
+    ('ぽ', "po"),
+    ('ま', "ma"),
+    ('み', "mi"),
+    ('む', "mu"),
+    ('め', "me"),
+    ('も', "mo"),
+    ('や', "ya"),
+    ('ゆ', "yu"),
+    ('よ', "yo"),
+    ('ゃ', "xya"),
+    ('ゅ', "xyu"),
+    ('ょ', "xyo"),
+    ('ら', "ra"),
+    ('り', "ri"),
+    ('る', "ru"),
+    ('れ', "re"),
+    ('ろ', "ro"),
+    ('わ', "wa"),
+    ('ゐ', "wyi"),
+    ('ゑ', "wye"),
+    ('を', "wo"),
+    ('ゎ', "xwa"),
+    ('ん', "nn"),
+    ('ゔ', "vu"),
+    ('ゕ', "xka"),
+    ('ゖ', "xke"),
+    ('ー', "-"),
+    ('、', ","),
+    ('。', "."),
+    ('・', "/"),
+    ('！', "!"),
+    ('？', "?"),
+    ('「', "["),
+    ('」', "]"),
+];
+
+/// keyd/kanataでのキーの記法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dialect {
+    Keyd,
+    Kanata,
+}
+
+impl Dialect {
+    /// キーのラベルを、設定ファイルにおけるキーの名前に変換する
+    fn key_name(&self, label: char) -> String {
+        let name = match (self, label) {
+            (Dialect::Keyd, ';') => "semicolon",
+            (Dialect::Keyd, ',') => "comma",
+            (Dialect::Keyd, '.') => "dot",
+            (Dialect::Keyd, '/') => "slash",
+            (Dialect::Keyd, '-') => "minus",
+            (Dialect::Keyd, '=') => "equal",
+            (Dialect::Keyd, '[') => "leftbrace",
+            (Dialect::Keyd, ']') => "rightbrace",
+            (Dialect::Keyd, '\'') => "apostrophe",
+            (Dialect::Keyd, '\\') => "backslash",
+            (Dialect::Keyd, '`') | (Dialect::Kanata, '`') => "grave",
+            (Dialect::Keyd, '!') | (Dialect::Kanata, '!') => "S-1",
+            (Dialect::Keyd, '?') => "S-slash",
+            (Dialect::Kanata, '?') => "S-/",
+            _ => return label.to_string(),
+        };
+        name.to_string()
+    }
+
+    /// `c` をローマ字で入力するマクロを返す
+    fn macro_of(&self, c: char) -> anyhow::Result<String> {
+        let romaji = ROMAJI
+            .iter()
+            .find(|(v, _)| *v == c)
+            .map(|(_, romaji)| *romaji)
+            .ok_or_else(|| anyhow::anyhow!("char '{}': no romaji to type it", c))?;
+        let keys = romaji
+            .chars()
+            .map(|v| self.key_name(v))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Ok(match self {
+            Dialect::Keyd => format!("macro({})", keys),
+            Dialect::Kanata => format!("(macro {})", keys),
+        })
+    }
+}
+
+/// 1文字を入力する打鍵。単打か、複数のキーの同時押しのいずれかになる
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Binding {
+    /// 同時に押下するキーのラベル。単打の場合は1キーのみ
+    keys: Vec<char>,
+    char: char,
+}
+
+/// `keymap` の各文字を入力する打鍵を返す。
+///
+/// シフトを伴う打鍵は、シフトとして使うキーと対象のキーの同時押しとする
+fn bindings(keymap: &Keymap) -> anyhow::Result<Vec<Binding>> {
+    keymap
+        .sequences()
+        .iter()
+        .map(|seq| {
+            let strokes = seq.strokes();
+            if strokes.iter().filter(|v| !v.shifter).count() != 1 {
+                anyhow::bail!(
+                    "char '{}': only a tap or a chord can be exported",
+                    seq.char()
+                );
+            }
+
+            Ok(Binding {
+                keys: strokes
+                    .iter()
+                    .map(|v| linear::get_char_of_point(&v.point))
+                    .collect(),
+                char: seq.char(),
+            })
+        })
+        .collect()
+}
+
+/// `keymap` を、keydの設定ファイルに変換する。
+///
+/// 各文字は、その文字をローマ字で入力するマクロとして出力するため、IMEはローマ字入力にしておく必要がある。
+/// 同時押しは、`timeout` ミリ秒以内に押下したキーの組み合わせとして扱う
+pub fn keyd(keymap: &Keymap, timeout: u16) -> anyhow::Result<String> {
+    let dialect = Dialect::Keyd;
+    let mut lines = bindings(keymap)?
+        .iter()
+        .map(|binding| {
+            let keys = binding
+                .keys
+                .iter()
+                .map(|v| dialect.key_name(*v))
+                .collect::<Vec<_>>()
+                .join("+");
+            Ok(format!("{} = {}", keys, dialect.macro_of(binding.char)?))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    lines.sort();
+
+    Ok(format!(
+        "# Generated by keymap-generator. Use with romaji input of the IME.\n\
+         [ids]\n\
+         *\n\
+         \n\
+         [global]\n\
+         chord_timeout = {}\n\
+         \n\
+         [main]\n\
+         {}\n",
+        timeout,
+        lines.join("\n")
+    ))
+}
+
+/// `keymap` を、kanataの設定ファイルに変換する。
+///
+/// 出力の前提は[keyd]と同様である。同時押しは `defchordsv2` で定義する
+pub fn kanata(keymap: &Keymap, timeout: u16) -> anyhow::Result<String> {
+    let dialect = Dialect::Kanata;
+    let bindings = bindings(keymap)?;
+
+    // 単打・同時押しで利用するキーを、レイアウトの順序で並べる
+    let labels = layout::current()
+        .keys()
+        .iter()
+        .map(|v| v.label)
+        .filter(|label| bindings.iter().any(|v| v.keys.contains(label)))
+        .collect::<Vec<_>>();
+    let taps = labels
+        .iter()
+        .map(|label| {
+            match bindings
+                .iter()
+                .find(|v| v.keys.len() == 1 && v.keys[0] == *label)
+            {
+                Some(binding) => dialect.macro_of(binding.char),
+                None => Ok("_".to_string()),
+            }
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut chords = bindings
+        .iter()
+        .filter(|v| v.keys.len() > 1)
+        .map(|binding| {
+            let keys = binding
+                .keys
+                .iter()
+                .map(|v| dialect.key_name(*v))
+                .collect::<Vec<_>>()
+                .join(" ");
+            Ok(format!(
+                "  ({}) {} {} first-release ()",
+                keys,
+                dialect.macro_of(binding.char)?,
+                timeout
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    chords.sort();
+
+    Ok(format!(
+        ";; Generated by keymap-generator. Use with romaji input of the IME.\n\
+         (defcfg\n  concurrent-tap-hold yes\n)\n\
+         \n\
+         (defsrc {})\n\
+         \n\
+         (deflayer base\n  {}\n)\n\
+         \n\
+         (defchordsv2\n{}\n)\n",
+        labels
+            .iter()
+            .map(|v| dialect.key_name(*v))
+            .collect::<Vec<_>>()
+            .join(" "),
+        taps.join("\n  "),
+        chords.join("\n")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        char_def::Inventory, frequency_table::FrequencyTable, keymap::tests::generate_keymap,
+    };
+
+    use super::*;
+
+    #[test]
+    fn romaji_covers_presets() {
+        // arrange
+        let inventory = Inventory::load("extended").unwrap();
+
+        // act
+        let missing = inventory
+            .definitions()
+            .iter()
+            .flat_map(|v| v.chars())
+            .chain(['！', '？', '「', '」'])
+            .filter(|c| Dialect::Keyd.macro_of(*c).is_err())
+            .collect::<Vec<_>>();
+
+        // assert
+        assert!(missing.is_empty(), "no romaji for {:?}", missing);
+        assert_eq!(Dialect::Keyd.macro_of('？').unwrap(), "macro(S-slash)");
+        assert_eq!(Dialect::Kanata.macro_of('「').unwrap(), "(macro [)");
+    }
+
+    #[test]
+    fn export_chords_with_timeout() {
+        // arrange
+        let keymap = generate_keymap(&mut StdRng::seed_from_u64(1), &FrequencyTable::new());
+        let seq = keymap.get('ぱ').unwrap();
+        let keys = seq
+            .strokes()
+            .iter()
+            .map(|v| Dialect::Keyd.key_name(linear::get_char_of_point(&v.point)))
+            .collect::<Vec<_>>();
+
+        // act
+        let keyd = keyd(&keymap, 40).unwrap();
+        let kanata = kanata(&keymap, 40).unwrap();
+
+        // assert
+        assert!(keyd.contains("chord_timeout = 40\n"));
+        assert!(keyd.contains(&format!("\n{} = macro(p a)\n", keys.join("+"))));
+        assert_eq!(
+            keyd.lines().filter(|v| v.contains("macro(")).count(),
+            keymap.sequences().len()
+        );
+        assert!(kanata.contains(" (macro p a) 40 first-release ()"));
+    }
+}
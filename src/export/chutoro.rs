@@ -0,0 +1,50 @@
+use crate::keymap::Keymap;
+
+/// `keymap` を、chutoroの評価ツール向けの配列定義に変換する。
+///
+/// https://github.com/mobitan/chutoro/tree/main/tools
+///
+/// 各行は、文字とそれを入力するキーの列をタブで区切ったものとし、文字の順序で並べる。
+/// キーの列はQWERTYでのキーの文字で表す。このツールの配列定義では、複数のキーを並べたものは
+/// それらの同時押しを表すため、シフト・濁音シフトなどとの同時押しは、シフトキー、対象のキーの順に
+/// そのまま並べる。例えば、 `d` の単打で「ま」、 `k` と `d` の同時押しで「め」を入力する場合は、
+/// `ま\td` と `め\tkd` の行になる。
+///
+/// キーマップのすべての文字は、単打か2キーの同時押しで入力するため、順に押下するキーの列は出力しない
+pub fn export(keymap: &Keymap) -> String {
+    keymap
+        .sequences()
+        .iter()
+        .map(|seq| format!("{}\t{}\n", seq.char(), seq.to_char_sequence()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::keymap_file::load_keymap;
+
+    use super::*;
+
+    #[test]
+    fn export_sample_keymap() {
+        // arrange
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/export/testdata/keymap.json");
+        let keymap = load_keymap(&path).unwrap();
+
+        // act
+        let text = export(&keymap);
+
+        // assert
+        let rows = text.lines().collect::<Vec<_>>();
+        // 単打、シフト、濁音・半濁音・小書き、句読点、ゔの順に、キーマップの定義から手で求めたもの
+        for expected in [
+            "ま\td", "か\tkx", "ゆ\tdm", "が\tjx", "ぱ\tmg", "ぁ\tpx", "、\tkj", "。\tfd", "ゔ\tq",
+        ] {
+            assert!(rows.contains(&expected), "should contain {}", expected);
+        }
+        assert!(rows.windows(2).all(|v| v[0] < v[1]), "should be sorted");
+        assert!(rows.iter().all(|v| !v.contains(['[', ']'])));
+    }
+}
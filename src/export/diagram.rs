@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use crate::{
+    char_def,
+    keymap::Keymap,
+    layout::{self, Point},
+    score::Conjunction,
+};
+
+/// 1キーを描画する大きさ
+const KEY_SIZE: usize = 64;
+/// キーの間隔
+const KEY_GAP: usize = 4;
+
+/// 各特殊キーの役割と、その役割を表す表記・説明・色
+const ROLE_STYLES: [(Role, &str, &str, &str); 4] = [
+    (Role::Shift, "シ", "シフト", "#1f77b4"),
+    (Role::Turbid, "濁", "濁音シフト", "#2ca02c"),
+    (Role::SemiTurbid, "半", "半濁音シフト", "#d62728"),
+    (Role::Small, "小", "小書きシフト", "#9467bd"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Shift,
+    Turbid,
+    SemiTurbid,
+    Small,
+}
+
+/// `label` のキーが持つ特殊キーとしての役割を返す
+fn roles_of(label: char) -> Vec<Role> {
+    let roles = layout::current().roles();
+    [
+        (Role::Shift, [roles.left_shift, roles.right_shift]),
+        (Role::Turbid, [roles.left_turbid, roles.right_turbid]),
+        (
+            Role::SemiTurbid,
+            [roles.left_semiturbid, roles.right_semiturbid],
+        ),
+        (Role::Small, [roles.left_small, roles.right_small]),
+    ]
+    .iter()
+    .filter(|(_, labels)| labels.contains(&label))
+    .map(|(role, _)| *role)
+    .collect()
+}
+
+/// 役割の表記と色を返す
+fn style_of(role: Role) -> (&'static str, &'static str) {
+    ROLE_STYLES
+        .iter()
+        .find(|(v, _, _, _)| *v == role)
+        .map(|(_, mark, _, color)| (*mark, *color))
+        .expect("all roles should have a style")
+}
+
+/// XMLのテキストとして出力できるように変換する
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `conjunctions` を `keymap` で入力した場合の、キーごとの打鍵数を返す。
+///
+/// 各n-gramのすべての文字を、n-gramの出現回数だけ数える。n-gramは重なり合っているため、
+/// 値はテキストにおける実際の打鍵数ではなく、キー間の相対的な負荷として扱う
+pub fn key_loads(keymap: &Keymap, conjunctions: &[Conjunction]) -> HashMap<Point, u64> {
+    let chars = char_def::all_chars();
+    let mut loads = HashMap::new();
+
+    for conjunction in conjunctions {
+        for seq in conjunction
+            .text
+            .iter()
+            .filter_map(|v| keymap.get(chars[*v].1))
+        {
+            for stroke in seq.strokes() {
+                *loads.entry(stroke.point).or_insert(0) += conjunction.appearances as u64;
+            }
+        }
+    }
+
+    loads
+}
+
+/// 打鍵数の割合を、白から赤に向かう色に変換する
+fn heat_color(ratio: f64) -> String {
+    let lightness = 100.0 - 50.0 * ratio.clamp(0.0, 1.0);
+    format!("hsl(0, 85%, {:.1}%)", lightness)
+}
+
+/// `keymap` のすべての面を、1つのキーボードの図として描画したSVGを返す。
+///
+/// 各キーには、中央に無シフト面、左上にシフト面、右上に濁音、右下に半濁音、左下に小書きの文字を描画する。
+/// 特殊キーは枠の色で示す。 `loads` を指定した場合は、打鍵数に応じてキーを塗り分ける
+pub fn svg(keymap: &Keymap, loads: Option<&HashMap<Point, u64>>) -> String {
+    let keys = layout::current().keys();
+    let rows = keys.iter().map(|v| v.row).max().unwrap_or(0) + 1;
+    let cols = keys.iter().map(|v| v.col).max().unwrap_or(0) + 1;
+    let width = cols * (KEY_SIZE + KEY_GAP) + KEY_GAP;
+    let height = rows * (KEY_SIZE + KEY_GAP) + KEY_GAP;
+    let max_load = loads
+        .and_then(|v| v.values().max().copied())
+        .unwrap_or(0)
+        .max(1);
+    let defs = layout::linear::linear_layout()
+        .iter()
+        .zip(keymap.iter())
+        .collect::<HashMap<_, _>>();
+
+    let mut elements = Vec::new();
+    for key in keys {
+        let x = KEY_GAP + key.col * (KEY_SIZE + KEY_GAP);
+        let y = KEY_GAP + key.row * (KEY_SIZE + KEY_GAP);
+        let point = key.point();
+        let roles = roles_of(key.label);
+        let fill = match loads {
+            Some(loads) => heat_color(*loads.get(&point).unwrap_or(&0) as f64 / max_load as f64),
+            None => "#ffffff".to_string(),
+        };
+        let (stroke, stroke_width) = match roles.first() {
+            Some(role) => (style_of(*role).1, 3),
+            None => ("#888888", 1),
+        };
+
+        elements.push(format!(
+            r#"<g class="key"><rect x="{}" y="{}" width="{}" height="{}" rx="6" fill="{}" stroke="{}" stroke-width="{}"/>"#,
+            x, y, KEY_SIZE, KEY_SIZE, fill, stroke, stroke_width
+        ));
+        if let Some(loads) = loads {
+            elements.push(format!(
+                "<title>{}: {}</title>",
+                escape(&key.label.to_string()),
+                loads.get(&point).unwrap_or(&0)
+            ));
+        }
+
+        if let Some(def) = defs.get(&point) {
+            let faces = [
+                (
+                    Some(def.unshift()),
+                    "unshift",
+                    x + KEY_SIZE / 2,
+                    y + 40,
+                    "middle",
+                ),
+                (Some(def.shifted()), "shifted", x + 5, y + 16, "start"),
+                (def.turbid(), "turbid", x + KEY_SIZE - 5, y + 16, "end"),
+                (
+                    def.semiturbid(),
+                    "semiturbid",
+                    x + KEY_SIZE - 5,
+                    y + 58,
+                    "end",
+                ),
+                (def.small(), "small", x + 5, y + 58, "start"),
+            ];
+            for (c, class, x, y, anchor) in faces {
+                let Some(c) = c.filter(|v| *v != '　') else {
+                    continue;
+                };
+                elements.push(format!(
+                    r#"<text class="{}" x="{}" y="{}" text-anchor="{}">{}</text>"#,
+                    class,
+                    x,
+                    y,
+                    anchor,
+                    escape(&c.to_string())
+                ));
+            }
+        }
+
+        let caption = std::iter::once(key.label.to_string())
+            .chain(roles.iter().map(|v| style_of(*v).0.to_string()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        elements.push(format!(
+            r#"<text class="label" x="{}" y="{}" text-anchor="middle">{}</text></g>"#,
+            x + KEY_SIZE / 2,
+            y + KEY_SIZE - 4,
+            escape(&caption)
+        ));
+    }
+
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">
+<style>
+text {{ font-family: sans-serif; }}
+.unshift {{ font-size: 22px; }}
+.shifted {{ font-size: 13px; fill: #1f77b4; }}
+.turbid {{ font-size: 13px; fill: #2ca02c; }}
+.semiturbid {{ font-size: 13px; fill: #d62728; }}
+.small {{ font-size: 13px; fill: #9467bd; }}
+.label {{ font-size: 8px; fill: #666666; }}
+</style>
+{}
+</svg>
+"#,
+        elements.join("\n")
+    )
+}
+
+/// [svg]の図と凡例を含むHTMLを返す
+pub fn html(keymap: &Keymap, loads: Option<&HashMap<Point, u64>>) -> String {
+    let legend = ROLE_STYLES
+        .iter()
+        .map(|(_, mark, description, color)| {
+            format!(
+                r#"<li><span style="color: {}">■</span> {}: {}</li>"#,
+                color, mark, description
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="ja">
+<head>
+<meta charset="utf-8">
+<title>keymap</title>
+</head>
+<body>
+{}<p>中央: 無シフト面 / 左上: シフト面 / 右上: 濁音 / 右下: 半濁音 / 左下: 小書き{}</p>
+<ul>
+{}
+</ul>
+</body>
+</html>
+"#,
+        svg(keymap, loads),
+        if loads.is_some() {
+            " / 色: 相対的な打鍵数"
+        } else {
+            ""
+        },
+        legend
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        frequency_table::FrequencyTable, keymap::tests::generate_keymap, layout::linear, ngram,
+    };
+
+    use super::*;
+
+    #[test]
+    fn count_strokes_of_all_chars() {
+        // arrange
+        let keymap = generate_keymap(&mut StdRng::seed_from_u64(1), &FrequencyTable::new());
+        // count-ngrams -n 4 で、未知の文字で分割されたコーパス
+        let corpus = "text\tcount\nもはれる\t1\nはれると\t1\n天気です\t2\n";
+        let (conjunctions, _) = ngram::read_conjunctions(corpus.as_bytes(), true).unwrap();
+
+        // act
+        let loads = key_loads(&keymap, &conjunctions);
+
+        // assert
+        let expected = [
+            ('も', 1),
+            ('は', 2),
+            ('れ', 2),
+            ('る', 2),
+            ('と', 1),
+            ('で', 2),
+            ('す', 2),
+        ];
+        for (c, _) in expected {
+            for stroke in keymap.get(c).unwrap().strokes() {
+                assert!(loads.contains_key(&stroke.point), "{c} should be counted");
+            }
+        }
+        let total = expected
+            .iter()
+            .map(|(c, count)| keymap.get(*c).unwrap().strokes().len() as u64 * count)
+            .sum::<u64>();
+        assert_eq!(loads.values().sum::<u64>(), total);
+    }
+
+    #[test]
+    fn render_all_faces_and_roles() {
+        // arrange
+        let keymap = generate_keymap(&mut StdRng::seed_from_u64(1), &FrequencyTable::new());
+        let small = linear::get_char_of_point(&linear::get_left_small_shifter());
+        let loads = HashMap::from([(linear::get_left_small_shifter(), 10)]);
+
+        // act
+        let svg = svg(&keymap, Some(&loads));
+        let html = html(&keymap, None);
+
+        // assert
+        for def in keymap.iter() {
+            for c in def.chars().into_iter().filter(|v| *v != '　') {
+                assert!(
+                    svg.contains(&format!(">{}</text>", c)),
+                    "{c} should be drawn"
+                );
+            }
+        }
+        assert!(svg.contains(&format!("<title>{}: 10</title>", small)));
+        assert!(svg.contains(r#"fill="hsl(0, 85%, 50.0%)""#));
+        assert!(svg.contains("小</text>"));
+        assert!(html.contains("<svg "));
+        assert!(!html.contains("hsl("));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::keymap::Keymap;
+
+/// `keymap` の各文字を入力するキーの列と、その文字の組を、キーの列の順で返す。
+///
+/// シフトや濁音シフトなどとの同時押しは、シフトとして使うキー、対象のキーの順に入力するものとする
+fn rules(keymap: &Keymap) -> Vec<(String, char)> {
+    let mut rules = keymap
+        .sequences()
+        .iter()
+        .map(|seq| (seq.to_char_sequence(), seq.char()))
+        .collect::<Vec<_>>();
+    rules.sort();
+    rules
+}
+
+/// Emacs Lispの文字列リテラルに変換する
+fn lisp_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret.push('"');
+    ret
+}
+
+/// ひらがなをカタカナに変換する。ひらがな以外はそのまま返す
+fn katakana(c: char) -> char {
+    match c {
+        'ぁ'..='ゖ' => char::from_u32(c as u32 + 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// `keymap` を、Emacsの `quail-define-rules` を利用したQuailパッケージに変換する
+pub fn quail(keymap: &Keymap) -> String {
+    let rules = rules(keymap)
+        .iter()
+        .map(|(input, c)| format!(" ({} ?{})", lisp_string(input), c))
+        .collect::<Vec<_>>();
+
+    format!(
+        ";;; Generated by keymap-generator.\n\
+         (require 'quail)\n\
+         \n\
+         (quail-define-package\n \
+         \"japanese-keymap-generator\" \"Japanese\" \"KG\" t\n \
+         \"Japanese input method generated by keymap-generator.\"\n \
+         nil t t nil nil nil nil nil nil nil t)\n\
+         \n\
+         (quail-define-rules\n\
+         {})\n",
+        rules.join("\n")
+    )
+}
+
+/// `keymap` を、SKK(DDSKK)の `skk-rom-kana-rule-list` に追加するルールに変換する。
+///
+/// ひらがなは、カタカナとひらがなの組として出力する
+pub fn skk(keymap: &Keymap) -> String {
+    let rules = rules(keymap)
+        .iter()
+        .map(|(input, c)| {
+            let output = if katakana(*c) == *c {
+                lisp_string(&c.to_string())
+            } else {
+                format!(
+                    "({} . {})",
+                    lisp_string(&katakana(*c).to_string()),
+                    lisp_string(&c.to_string())
+                )
+            };
+            format!("   ({} nil {})", lisp_string(input), output)
+        })
+        .collect::<Vec<_>>();
+
+    format!(
+        ";;; Generated by keymap-generator.\n\
+         (setq skk-rom-kana-rule-list\n      \
+         (append skk-rom-kana-rule-list\n              \
+         '(\n\
+         {})))\n",
+        rules.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{frequency_table::FrequencyTable, keymap::tests::generate_keymap};
+
+    use super::*;
+
+    #[test]
+    fn escape_lisp_string() {
+        // arrange
+
+        // act
+        let ret = lisp_string("a\"b\\");
+
+        // assert
+        assert_eq!(ret, "\"a\\\"b\\\\\"");
+    }
+
+    #[test]
+    fn export_sorted_rules() {
+        // arrange
+        let keymap = generate_keymap(&mut StdRng::seed_from_u64(1), &FrequencyTable::new());
+        let seq = keymap.get('ぱ').unwrap();
+        let input = lisp_string(&seq.to_char_sequence());
+
+        // act
+        let quail = quail(&keymap);
+        let skk = skk(&keymap);
+
+        // assert
+        let lines = quail
+            .lines()
+            .filter(|v| v.starts_with(" (\""))
+            .collect::<Vec<_>>();
+        let mut sorted = lines.clone();
+        sorted.sort();
+        assert_eq!(lines, sorted);
+        assert_eq!(lines.len(), keymap.sequences().len());
+        assert!(quail.contains(&format!("\n ({} ?ぱ)\n", input)));
+        assert!(skk.contains(&format!("\n   ({} nil (\"パ\" . \"ぱ\"))", input)));
+        assert!(skk.contains(" nil \"、\")"));
+        assert!(skk.contains(" nil (\"ヴ\" . \"ゔ\"))"));
+    }
+}
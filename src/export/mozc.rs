@@ -0,0 +1,154 @@
+use crate::keymap::Keymap;
+
+/// 入力と出力の組からなる、ローマ字テーブルの行
+type Row = (String, char);
+
+/// `keymap` を、Google日本語入力(Mozc)のローマ字テーブルとしてインポートできるTSVに変換する。
+///
+/// 各行は、入力・出力・次の入力の3列からなる。シフトや濁音シフトなどとの同時押しは、
+/// シフトとして使うキー、対象のキーの順に入力する文字列として表す。次の入力は利用しないため、常に空とする。
+///
+/// ローマ字テーブルは同時押しを表現できないため、シフトとして使うキーの単打の入力(例えば `k` )は、
+/// そのキーから始まる同時押しの入力(例えば `kx` )の接頭辞になる。Mozcは次のキーを待つため、
+/// `k` の単打の文字に続けて `x` の単打の文字を入力すると、`kx` の文字に変換されてしまう。
+/// このような組み合わせは警告として出力する。同時押しとして入力するには、keyd/kanataの出力を利用する
+pub fn export(keymap: &Keymap) -> String {
+    let mut rows = keymap
+        .sequences()
+        .iter()
+        .map(|seq| (seq.to_char_sequence(), seq.char()))
+        .collect::<Vec<_>>();
+    rows.sort();
+
+    let collisions = prefix_collisions(&rows);
+    if !collisions.is_empty() {
+        log::warn!(
+            "{} inputs are prefixes of other inputs, so typing them in a row yields another char: {}. Use keyd or kanata export to type them as chords",
+            collisions.len(),
+            collisions
+                .iter()
+                .map(|((short, short_char), (long, long_char))| {
+                    format!("{}({})->{}({})", short, short_char, long, long_char)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    rows.iter()
+        .map(|(input, output)| format!("{}\t{}\t\n", input, output))
+        .collect()
+}
+
+/// 入力が他の行の入力の接頭辞になる組を、(接頭辞になる行, その接頭辞で始まる行)として返す
+///
+/// `rows` は入力の順に並んでいること
+fn prefix_collisions(rows: &[Row]) -> Vec<(&Row, &Row)> {
+    rows.iter()
+        .enumerate()
+        .flat_map(|(idx, short)| {
+            // 入力の順に並んでいるため、接頭辞で始まる行は直後に連続する
+            rows[idx + 1..]
+                .iter()
+                .take_while(|long| long.0.starts_with(&short.0))
+                .map(move |long| (short, long))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        path::Path,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        char_def, frequency_table::FrequencyTable, keymap::tests::generate_keymap,
+        keymap_file::load_keymap,
+    };
+
+    use super::*;
+
+    #[test]
+    fn round_trip_with_keymap() {
+        // arrange
+        let keymap = generate_keymap(&mut StdRng::seed_from_u64(1), &FrequencyTable::new());
+
+        // act
+        let text = export(&keymap);
+        let rows = text
+            .lines()
+            .map(|line| {
+                let columns = line.split('\t').collect::<Vec<_>>();
+                assert_eq!(columns.len(), 3, "should have 3 columns: {line}");
+                (columns[0].to_string(), columns[1].chars().next().unwrap())
+            })
+            .collect::<Vec<_>>();
+        let outputs = rows
+            .iter()
+            .map(|(input, output)| (*output, input.clone()))
+            .collect::<HashMap<_, _>>();
+
+        // assert
+        let inputs = rows.iter().map(|(v, _)| v).collect::<HashSet<_>>();
+        assert_eq!(inputs.len(), rows.len(), "input should be unique");
+        assert!(
+            !outputs.contains_key(&'　'),
+            "empty faces should not be exported"
+        );
+        for c in char_def::all_chars().iter().map(|(_, c)| *c).chain(['ゔ']) {
+            assert_eq!(
+                outputs.get(&c),
+                keymap.get(c).map(|v| v.to_char_sequence()).as_ref(),
+                "{c} should be typed as in keymap"
+            );
+        }
+    }
+
+    #[test]
+    fn export_sample_keymap() {
+        // arrange
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/export/testdata/keymap.json");
+        let keymap = load_keymap(&path).unwrap();
+
+        // act
+        let text = export(&keymap);
+
+        // assert
+        let rows = text.lines().collect::<Vec<_>>();
+        // 単打、シフト、濁音・半濁音・小書き、句読点、ゔの順に、キーマップの定義から手で求めたもの
+        for expected in [
+            "d\tま\t",
+            "kx\tか\t",
+            "dm\tゆ\t",
+            "jx\tが\t",
+            "mg\tぱ\t",
+            "px\tぁ\t",
+            "kj\t、\t",
+            "fd\t。\t",
+            "q\tゔ\t",
+        ] {
+            assert!(rows.contains(&expected), "should contain {}", expected);
+        }
+    }
+
+    #[test]
+    fn find_prefix_collisions() {
+        // arrange
+        let rows = [
+            ("k".to_string(), 'ぬ'),
+            ("ka".to_string(), 'い'),
+            ("kx".to_string(), 'か'),
+            ("x".to_string(), 'あ'),
+        ];
+
+        // act
+        let collisions = prefix_collisions(&rows);
+
+        // assert
+        assert_eq!(collisions, vec![(&rows[0], &rows[1]), (&rows[0], &rows[2])]);
+    }
+}
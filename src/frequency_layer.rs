@@ -6,7 +6,7 @@ use crate::char_def::{self, CharDef};
 /// 使用済みのキープール。サイズは[char_def::definitions]と同一で、trueであれば使用済みである
 pub type UsedKeyPool = Vec<bool>;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Layer {
     /// 各キーにおける文字の頻度
     frequencies: Vec<f64>,
@@ -67,6 +67,17 @@ impl Layer {
         self.total = self.frequencies.iter().sum();
     }
 
+    /// 各文字が選択される確率を、確率が高い順に返す
+    fn probabilities(&self) -> Vec<(CharDef, f64)> {
+        let mut ret = char_def::definitions()
+            .into_iter()
+            .zip(self.frequencies.iter().map(|v| v / self.total))
+            .collect::<Vec<_>>();
+
+        ret.sort_by(|(_, v1), (_, v2)| v2.total_cmp(v1));
+        ret
+    }
+
     /// 確率に応じて、文字の定義を返す
     ///
     /// 利用可能なキーがない場合はNoneを返す
@@ -101,7 +112,7 @@ impl Layer {
 }
 
 /// 頻度レイヤーを束ねたもの。各キー毎にわりあてられる。
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LayeredFrequency {
     /// 頻度レイヤーのリスト
     layers: Vec<Layer>,
@@ -190,6 +201,14 @@ impl LayeredFrequency {
         }
     }
 
+    /// layerの名前と、そのlayerで各文字が選択される確率を返す
+    pub fn probabilities(&self) -> Vec<(String, Vec<(CharDef, f64)>)> {
+        self.layers
+            .iter()
+            .map(|layer| (layer.name.clone(), layer.probabilities()))
+            .collect()
+    }
+
     pub fn mutate(&mut self, rng: &mut StdRng) {
         for layer in self.layers.iter_mut() {
             layer.mutate(rng)
@@ -7,7 +7,7 @@ use crate::{
     char_def::{self},
     frequency_layer::{LayeredCharCombination, LayeredFrequency, UsedKeyPool},
     keymap::Keymap,
-    layout::linear::{linear_layout, LINEAR_L_SHIFT_INDEX, LINEAR_R_SHIFT_INDEX},
+    layout::linear::{l_shift_index, linear_layout, r_shift_index},
 };
 
 pub const NORMAL_LAYER: &str = "normal";
@@ -129,7 +129,7 @@ impl KeyAssigner {
     ///
     /// この関数は、right_shift_keyとセットで利用することを前提としている。
     pub fn left_shift_key(&mut self, rng: &mut StdRng) -> LayeredCharCombination {
-        let freq = &self.layered_combinations[LINEAR_L_SHIFT_INDEX];
+        let freq = &self.layered_combinations[l_shift_index()];
 
         let char = freq.get_assignment(
             rng,
@@ -161,7 +161,7 @@ impl KeyAssigner {
         rng: &mut StdRng,
         left_combination: &LayeredCharCombination,
     ) -> LayeredCharCombination {
-        let freq = &self.layered_combinations[LINEAR_R_SHIFT_INDEX];
+        let freq = &self.layered_combinations[r_shift_index()];
 
         // シフトキーは、シフト面が同一であることが要件になる。
         let preds = vec![|comb: &LayeredCharCombination| {
@@ -189,7 +189,7 @@ impl KeyAssigner {
 /// 条件に一致する文字の組み合わせを返す
 
 /// キーの出現回数を記録するテーブル
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct FrequencyTable {
     // 各キーごとに、どの文字がどれだけ出現したかを記録する
     //
@@ -209,7 +209,7 @@ impl Default for FrequencyTable {
 impl FrequencyTable {
     /// 頻度表を新規に作成する。
     pub fn new() -> Self {
-        // 可能なキーの位置は26個なので、その分の分布を設定する
+        // レイアウトで文字を割り当てるキーの数だけ分布を設定する
         // 句読点は特殊なキーに割り当てられるため、それらは除外する
         let combinations = vec![LayeredFrequency::new(&LAYERS); linear_layout().len()];
 
@@ -223,6 +223,22 @@ impl FrequencyTable {
         }
     }
 
+    /// 頻度表が、現在の文字の一覧に対して作成されたものかどうかを返す
+    pub fn matches_chars(&self) -> bool {
+        let definitions = char_def::definitions();
+
+        self.character_map.len() == definitions.len()
+            && definitions
+                .iter()
+                .enumerate()
+                .all(|(idx, v)| self.character_map.get(&v.normal()) == Some(&idx))
+    }
+
+    /// キーごとの頻度を、[linear_layout]の順序で返す
+    pub fn frequencies(&self) -> &[LayeredFrequency] {
+        &self.frequency
+    }
+
     /// `keymap` にある文字から、頻度表を更新する
     pub fn update(&mut self, best_keymap: &Keymap, learning_rate: f64) {
         for (key_idx, def) in best_keymap.iter().enumerate() {
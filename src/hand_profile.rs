@@ -0,0 +1,260 @@
+use std::{collections::HashMap, fs, path::Path, sync::OnceLock};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::layout::{self, Finger, Hand, Layout, Point};
+
+/// 組み込みのプロファイル。名前とTOMLの組
+const PRESETS: [(&str, &str); 4] = [
+    ("standard", include_str!("../profiles/standard.toml")),
+    ("angled", include_str!("../profiles/angled.toml")),
+    (
+        "b-right-index",
+        include_str!("../profiles/b-right-index.toml"),
+    ),
+    ("avoid-pinky", include_str!("../profiles/avoid-pinky.toml")),
+];
+
+/// キーごとの上書き
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyOverride {
+    label: char,
+    hand: Option<Hand>,
+    finger: Option<Finger>,
+    weight: Option<u16>,
+}
+
+/// 指ごとの負荷の倍率
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FingerFactor {
+    /// 省略した場合は両手に適用する
+    hand: Option<Hand>,
+    finger: Finger,
+    factor: f64,
+}
+
+/// プロファイルファイルの内容
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileDefinition {
+    #[serde(default)]
+    keys: Vec<KeyOverride>,
+    #[serde(default)]
+    fingers: Vec<FingerFactor>,
+}
+
+/// キーを押下する手・指と、その負荷
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FingerAssignment {
+    pub hand: Hand,
+    pub finger: Finger,
+    pub weight: u16,
+}
+
+/// 打鍵する人の手の使い方。
+///
+/// レイアウトに定義した手・指・負荷を基本とし、プロファイルファイルでキーごと・指ごとに調整する。
+/// 記述例は `profiles/standard.toml` を参照。
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandProfile {
+    assignments: HashMap<Point, FingerAssignment>,
+}
+
+impl HandProfile {
+    /// レイアウトの定義をそのまま利用するプロファイルを返す
+    pub fn standard(layout: &Layout) -> HandProfile {
+        HandProfile::resolve(&ProfileDefinition::default(), layout)
+            .expect("empty profile should be valid")
+    }
+
+    /// 組み込みのプロファイルの名前を返す
+    pub fn presets() -> impl Iterator<Item = &'static str> {
+        PRESETS.iter().map(|(name, _)| *name)
+    }
+
+    /// 組み込みのプロファイル名、またはファイルのパスからプロファイルを読み込む
+    pub fn load(name_or_path: &str, layout: &Layout) -> anyhow::Result<HandProfile> {
+        let text = match PRESETS.iter().find(|(name, _)| *name == name_or_path) {
+            Some((_, text)) => text.to_string(),
+            None => fs::read_to_string(Path::new(name_or_path)).with_context(|| {
+                format!(
+                    "hand profile {} is neither a preset ({}) nor a readable file",
+                    name_or_path,
+                    HandProfile::presets().collect::<Vec<_>>().join(", ")
+                )
+            })?,
+        };
+
+        HandProfile::parse(&text, layout)
+            .with_context(|| format!("invalid hand profile {}", name_or_path))
+    }
+
+    /// TOMLの文字列からプロファイルを読み込む
+    pub fn parse(text: &str, layout: &Layout) -> anyhow::Result<HandProfile> {
+        let definition: ProfileDefinition = toml::from_str(text)?;
+        HandProfile::resolve(&definition, layout)
+    }
+
+    fn resolve(definition: &ProfileDefinition, layout: &Layout) -> anyhow::Result<HandProfile> {
+        let mut assignments = layout
+            .keys()
+            .iter()
+            .map(|key| {
+                (
+                    key.point(),
+                    FingerAssignment {
+                        hand: key.hand,
+                        finger: key.finger,
+                        weight: key.weight,
+                    },
+                )
+            })
+            .collect::<HashMap<_, _>>();
+
+        for key in definition.keys.iter() {
+            let point = layout
+                .key_of_label(key.label)
+                .map(|v| v.point())
+                .ok_or_else(|| anyhow::anyhow!("key '{}': not in the layout", key.label))?;
+            let assignment = assignments
+                .get_mut(&point)
+                .expect("key should be in layout");
+
+            assignment.hand = key.hand.unwrap_or(assignment.hand);
+            assignment.finger = key.finger.unwrap_or(assignment.finger);
+            assignment.weight = key.weight.unwrap_or(assignment.weight);
+        }
+
+        for factor in definition.fingers.iter() {
+            if !factor.factor.is_finite() || factor.factor <= 0.0 {
+                anyhow::bail!(
+                    "fingers.factor should be positive, but got {}",
+                    factor.factor
+                );
+            }
+
+            for assignment in assignments.values_mut().filter(|v| {
+                v.finger == factor.finger && factor.hand.is_none_or(|hand| hand == v.hand)
+            }) {
+                let weight = (assignment.weight as f64 * factor.factor).round();
+                assignment.weight = weight.min(u16::MAX as f64) as u16;
+            }
+        }
+
+        Ok(HandProfile { assignments })
+    }
+
+    /// 指定された位置のキーを押下する手・指を返す
+    #[inline]
+    pub fn assignment(&self, point: &Point) -> &FingerAssignment {
+        self.assignments
+            .get(point)
+            .expect("point should be in the layout")
+    }
+}
+
+static SELECTED: OnceLock<String> = OnceLock::new();
+
+static CURRENT: OnceLock<HandProfile> = OnceLock::new();
+
+/// プロセス全体で利用するプロファイルを、組み込みのプロファイル名またはファイルのパスで選択する。
+///
+/// プロファイルはレイアウトに依存するため、[resolve_current]が最初に呼ばれた時点のレイアウトで読み込む。
+/// 一度しか選択できないため、[selected]や[resolve_current]が呼ばれる前に選択する必要がある
+pub fn select(name_or_path: &str) -> anyhow::Result<()> {
+    let selected = SELECTED.get_or_init(|| name_or_path.to_string());
+
+    if selected != name_or_path {
+        anyhow::bail!("another hand profile is already in use");
+    }
+    Ok(())
+}
+
+/// 選択されたプロファイルの名前またはパスを返す。選択されていない場合はstandardを利用する
+pub fn selected() -> &'static str {
+    SELECTED.get_or_init(|| PRESETS[0].0.to_string())
+}
+
+/// 選択されたプロファイルを、現在のレイアウトで読み込んで返す。
+///
+/// 読み込んだプロファイルはプロセス全体で共有し、キーマップの打鍵の構築と評価の両方で利用する
+pub fn resolve_current() -> anyhow::Result<&'static HandProfile> {
+    if let Some(profile) = CURRENT.get() {
+        return Ok(profile);
+    }
+
+    let profile = HandProfile::load(selected(), layout::current())?;
+    Ok(CURRENT.get_or_init(|| profile))
+}
+
+/// 現在のプロファイルを返す。読み込めない場合はpanicするため、事前に[resolve_current]を呼び出しておく
+pub fn current() -> &'static HandProfile {
+    resolve_current().expect("hand profile should be resolved before use")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        // arrange
+        let layout = Layout::default();
+
+        // act
+        let profiles = PRESETS
+            .iter()
+            .map(|(name, _)| HandProfile::load(name, &layout))
+            .collect::<anyhow::Result<Vec<_>>>();
+
+        // assert
+        assert_eq!(profiles.unwrap()[0], HandProfile::standard(&layout));
+    }
+
+    #[test]
+    fn override_key_and_finger_factor() {
+        // arrange
+        let layout = Layout::default();
+        let text = r#"
+            [[keys]]
+            label = "b"
+            hand = "right"
+
+            [[fingers]]
+            hand = "left"
+            finger = "pinky"
+            factor = 2.0
+        "#;
+        let point_of = |label| layout.key_of_label(label).unwrap().point();
+
+        // act
+        let profile = HandProfile::parse(text, &layout).unwrap();
+
+        // assert
+        assert_eq!(
+            *profile.assignment(&point_of('b')),
+            FingerAssignment {
+                hand: Hand::Right,
+                finger: Finger::Index,
+                weight: 170
+            }
+        );
+        assert_eq!(profile.assignment(&point_of('a')).weight, 194);
+        assert_eq!(profile.assignment(&point_of(';')).weight, 97);
+    }
+
+    #[test]
+    fn reject_unknown_key() {
+        // arrange
+        let layout = Layout::default();
+
+        // act
+        let result = HandProfile::parse("[[keys]]\nlabel = \"t\"\nfinger = \"index\"", &layout);
+
+        // assert
+        assert!(result.is_err());
+    }
+}
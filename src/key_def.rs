@@ -1,13 +1,20 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{char_def::CharDef, frequency_layer::LayeredCharCombination};
 
 /// キー自体の基本定義。
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct KeyDef {
     unshift: Option<CharDef>,
     shifted: Option<CharDef>,
 }
 
 impl KeyDef {
+    /// 無シフト面とシフト面の文字定義から[KeyDef]を生成する
+    pub fn new(unshift: Option<CharDef>, shifted: Option<CharDef>) -> Self {
+        KeyDef { unshift, shifted }
+    }
+
     /// 無シフトに対して[def]を設定した[KeyDef]を返す
     pub fn from_combination(combination: &LayeredCharCombination) -> Self {
         KeyDef {
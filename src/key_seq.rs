@@ -1,4 +1,34 @@
-use crate::layout::{self, Point};
+use crate::{
+    hand_profile::HandProfile,
+    layout::{self, Point},
+};
+
+/// 1回の物理的な打鍵
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Stroke {
+    /// 押下するキーの位置
+    pub point: Point,
+    /// 次の打鍵の間押し続けるシフトキーとしての打鍵かどうか
+    pub shifter: bool,
+}
+
+impl Stroke {
+    /// 単打の打鍵を返す
+    pub fn tap(point: Point) -> Self {
+        Stroke {
+            point,
+            shifter: false,
+        }
+    }
+
+    /// シフトキーとしての打鍵を返す
+    pub fn shifter(point: Point) -> Self {
+        Stroke {
+            point,
+            shifter: true,
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum KeyPressPattern {
@@ -21,14 +51,15 @@ impl KeySeq {
     /// # Arguments
     /// * `char` - 入力する文字
     /// * `key_pos` - キーを押下する順序
+    /// * `profile` - キーを押下する手。シフトキーは `key_pos` と反対の手のものを利用する
     ///
     /// # Returns
     /// 新しいKeySeq
-    pub fn from_shift(char: char, key_pos: &Point) -> Self {
+    pub fn from_shift(char: char, key_pos: &Point, profile: &HandProfile) -> Self {
         let layout = layout::linear::linear_layout();
-        let shift_key = match layout::linear::get_hand_of_point(key_pos) {
-            layout::Hand::Right => layout[layout::linear::LINEAR_L_SHIFT_INDEX],
-            layout::Hand::Left => layout[layout::linear::LINEAR_R_SHIFT_INDEX],
+        let shift_key = match profile.assignment(key_pos).hand {
+            layout::Hand::Right => layout[layout::linear::l_shift_index()],
+            layout::Hand::Left => layout[layout::linear::r_shift_index()],
         };
 
         KeySeq {
@@ -113,15 +144,16 @@ impl KeySeq {
         self.char
     }
 
-    /// `sequence` を返す
-    pub fn as_raw_sequence(&self) -> (Point, Option<Point>) {
+    /// 文字を入力するための物理的な打鍵を、押下する順に返す
+    ///
+    /// シフトを伴う場合は、シフトキーの打鍵の後に、シフトキーを押したまま押下するキーの打鍵が続く
+    pub fn strokes(&self) -> Vec<Stroke> {
         self.sequence
             .iter()
-            .map(|seq| match seq {
-                KeyPressPattern::Shift(f, s) => (*f, Some(*s)),
-                KeyPressPattern::Sequential(p) => (*p, None),
+            .flat_map(|seq| match seq {
+                KeyPressPattern::Shift(f, s) => vec![Stroke::shifter(*f), Stroke::tap(*s)],
+                KeyPressPattern::Sequential(p) => vec![Stroke::tap(*p)],
             })
-            .take(1)
-            .collect::<Vec<_>>()[0]
+            .collect()
     }
 }
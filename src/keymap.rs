@@ -4,19 +4,24 @@ use std::{
 };
 
 use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     frequency_table::KeyAssigner,
+    hand_profile::{self, HandProfile},
     key_def::KeyDef,
     key_seq::KeySeq,
-    layout::linear::{
-        self, get_left_small_shifter, get_right_small_shifter, linear_layout,
-        LINEAR_L_SEMITURBID_INDEX, LINEAR_L_SHIFT_INDEX, LINEAR_L_TURBID_INDEX,
-        LINEAR_R_SEMITURBID_INDEX, LINEAR_R_SHIFT_INDEX, LINEAR_R_TURBID_INDEX,
+    layout::{
+        self,
+        linear::{
+            self, get_left_small_shifter, get_right_small_shifter, l_semiturbid_index,
+            l_shift_index, l_turbid_index, linear_layout, r_semiturbid_index, r_shift_index,
+            r_turbid_index,
+        },
     },
 };
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 enum KeyAssignment {
     /// 割当済。変更することも出来る。
     A(KeyDef),
@@ -31,8 +36,8 @@ mod constraints {
     use crate::{
         char_def::{self, definitions, CharDef},
         layout::linear::{
-            LINEAR_L_SEMITURBID_INDEX, LINEAR_L_SHIFT_INDEX, LINEAR_L_TURBID_INDEX,
-            LINEAR_R_SEMITURBID_INDEX, LINEAR_R_SHIFT_INDEX, LINEAR_R_TURBID_INDEX,
+            l_semiturbid_index, l_shift_index, l_turbid_index, r_semiturbid_index, r_shift_index,
+            r_turbid_index,
         },
     };
 
@@ -40,8 +45,8 @@ mod constraints {
 
     /// 左右のシフトキーに割り当てられている文字が同一であるか確認する
     pub(super) fn should_shift_having_same_key(layout: &[KeyAssignment]) -> bool {
-        let left_shifted = &layout[LINEAR_L_SHIFT_INDEX];
-        let right_shifted = &layout[LINEAR_R_SHIFT_INDEX];
+        let left_shifted = &layout[l_shift_index()];
+        let right_shifted = &layout[r_shift_index()];
 
         match (left_shifted, right_shifted) {
             (KeyAssignment::A(l), KeyAssignment::A(r)) => l.shifted() == r.shifted(),
@@ -51,8 +56,8 @@ mod constraints {
 
     /// 左右のシフトキーには清音しか設定されていないかどうかを確認する
     pub(super) fn should_shift_only_clear_tones(layout: &[KeyAssignment]) -> bool {
-        let left_shifted = &layout[LINEAR_L_SHIFT_INDEX];
-        let right_shifted = &layout[LINEAR_R_SHIFT_INDEX];
+        let left_shifted = &layout[l_shift_index()];
+        let right_shifted = &layout[r_shift_index()];
 
         let cleartones = definitions()
             .into_iter()
@@ -106,8 +111,8 @@ mod constraints {
 
     /// 濁音シフトには、濁音が一つ以下しか設定されていないかどうかを確認する
     pub(super) fn should_have_only_one_turbid_in_turbid_shifts(layout: &[KeyAssignment]) -> bool {
-        let left = &layout[LINEAR_L_TURBID_INDEX];
-        let right = &layout[LINEAR_R_TURBID_INDEX];
+        let left = &layout[l_turbid_index()];
+        let right = &layout[r_turbid_index()];
 
         match (left, right) {
             (KeyAssignment::A(left), KeyAssignment::A(right)) => {
@@ -137,8 +142,8 @@ mod constraints {
     pub(super) fn should_have_only_one_semiturbid_in_semiturbid_shifts(
         layout: &[KeyAssignment],
     ) -> bool {
-        let left = &layout[LINEAR_L_SEMITURBID_INDEX];
-        let right = &layout[LINEAR_R_SEMITURBID_INDEX];
+        let left = &layout[l_semiturbid_index()];
+        let right = &layout[r_semiturbid_index()];
 
         match (left, right) {
             (KeyAssignment::A(left), KeyAssignment::A(right)) => {
@@ -169,8 +174,8 @@ mod constraints {
         layout: &[KeyAssignment],
     ) -> bool {
         [
-            (LINEAR_L_TURBID_INDEX, LINEAR_R_SEMITURBID_INDEX),
-            (LINEAR_R_TURBID_INDEX, LINEAR_L_SEMITURBID_INDEX),
+            (l_turbid_index(), r_semiturbid_index()),
+            (r_turbid_index(), l_semiturbid_index()),
         ]
         .iter()
         .all(|(turbid, semiturbid)| {
@@ -286,12 +291,12 @@ mod constraints {
             put_key(
                 &mut layout,
                 KeyDef::from_combination(&comb1),
-                LINEAR_L_SHIFT_INDEX,
+                l_shift_index(),
             );
             put_key(
                 &mut layout,
                 KeyDef::from_combination(&comb2),
-                LINEAR_R_SHIFT_INDEX,
+                r_shift_index(),
             );
 
             // act
@@ -319,12 +324,12 @@ mod constraints {
             put_key(
                 &mut layout,
                 KeyDef::from_combination(&comb1),
-                LINEAR_L_SHIFT_INDEX,
+                l_shift_index(),
             );
             put_key(
                 &mut layout,
                 KeyDef::from_combination(&comb2),
-                LINEAR_R_SHIFT_INDEX,
+                r_shift_index(),
             );
 
             // act
@@ -353,12 +358,12 @@ mod constraints {
             put_key(
                 &mut layout,
                 KeyDef::from_combination(&comb1),
-                LINEAR_L_SHIFT_INDEX,
+                l_shift_index(),
             );
             put_key(
                 &mut layout,
                 KeyDef::from_combination(&comb2),
-                LINEAR_R_SHIFT_INDEX,
+                r_shift_index(),
             );
 
             // act
@@ -371,12 +376,42 @@ mod constraints {
 }
 
 /// 有効なキーマップ
-#[derive(Debug, PartialEq, Eq, Clone)]
+///
+/// シリアライズする際にはlayoutのみを保存し、復元時に制約の確認とsequenceの再構築を行う
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(into = "Vec<KeyAssignment>", try_from = "Vec<KeyAssignment>")]
 pub struct Keymap {
     layout: Vec<KeyAssignment>,
     sequences: HashMap<char, KeySeq>,
 }
 
+impl From<Keymap> for Vec<KeyAssignment> {
+    fn from(value: Keymap) -> Self {
+        value.layout
+    }
+}
+
+impl TryFrom<Vec<KeyAssignment>> for Keymap {
+    type Error = anyhow::Error;
+
+    fn try_from(layout: Vec<KeyAssignment>) -> Result<Self, Self::Error> {
+        if layout.len() != linear_layout().len() {
+            anyhow::bail!(
+                "keymap should have {} keys, but {}",
+                linear_layout().len(),
+                layout.len()
+            );
+        }
+
+        if !Keymap::meet_requirements(&layout) {
+            anyhow::bail!("keymap does not meet requirements");
+        }
+
+        let sequences = Keymap::build_sequences_with(&layout, hand_profile::resolve_current()?);
+        Ok(Keymap { layout, sequences })
+    }
+}
+
 impl Keymap {
     /// 指定されたseedを元にしてキーマップを生成する
     ///
@@ -387,20 +422,20 @@ impl Keymap {
 
         // まずシフトキーに対して割り当てる
         let left = assigner.left_shift_key(rng);
-        layout[LINEAR_L_SHIFT_INDEX] = KeyAssignment::A(KeyDef::from_combination(&left));
+        layout[l_shift_index()] = KeyAssignment::A(KeyDef::from_combination(&left));
         let right = assigner.right_shift_key(rng, &left);
-        layout[LINEAR_R_SHIFT_INDEX] = KeyAssignment::A(KeyDef::from_combination(&right));
-        layout[LINEAR_L_TURBID_INDEX] = KeyAssignment::A(KeyDef::from_combination(
-            &assigner.pick_key(rng, LINEAR_L_TURBID_INDEX),
+        layout[r_shift_index()] = KeyAssignment::A(KeyDef::from_combination(&right));
+        layout[l_turbid_index()] = KeyAssignment::A(KeyDef::from_combination(
+            &assigner.pick_key(rng, l_turbid_index()),
         ));
-        layout[LINEAR_R_TURBID_INDEX] = KeyAssignment::A(KeyDef::from_combination(
-            &assigner.pick_key(rng, LINEAR_R_TURBID_INDEX),
+        layout[r_turbid_index()] = KeyAssignment::A(KeyDef::from_combination(
+            &assigner.pick_key(rng, r_turbid_index()),
         ));
-        layout[LINEAR_L_SEMITURBID_INDEX] = KeyAssignment::A(KeyDef::from_combination(
-            &assigner.pick_key(rng, LINEAR_L_SEMITURBID_INDEX),
+        layout[l_semiturbid_index()] = KeyAssignment::A(KeyDef::from_combination(
+            &assigner.pick_key(rng, l_semiturbid_index()),
         ));
-        layout[LINEAR_R_SEMITURBID_INDEX] = KeyAssignment::A(KeyDef::from_combination(
-            &assigner.pick_key(rng, LINEAR_R_SEMITURBID_INDEX),
+        layout[r_semiturbid_index()] = KeyAssignment::A(KeyDef::from_combination(
+            &assigner.pick_key(rng, r_semiturbid_index()),
         ));
 
         // 各場所にassignする
@@ -416,39 +451,63 @@ impl Keymap {
         }
     }
 
-    /// charとsequenceのmappingを生成する
+    /// キーごとの定義からキーマップを生成する
+    ///
+    /// # Arguments
+    /// * `defs` - [linear_layout]の順序に並べたキーの定義
+    ///
+    /// # Returns
+    /// 制約を満たさない場合はエラー
+    pub fn from_key_defs(defs: Vec<KeyDef>) -> anyhow::Result<Keymap> {
+        Keymap::try_from(defs.into_iter().map(KeyAssignment::A).collect::<Vec<_>>())
+    }
+
+    /// charとsequenceのmappingを、現在のプロファイルに従って生成する
     fn build_sequences(layout: &[KeyAssignment]) -> HashMap<char, KeySeq> {
+        Keymap::build_sequences_with(layout, hand_profile::current())
+    }
+
+    /// charとsequenceのmappingを生成する
+    ///
+    /// シフト・濁音・半濁音・小書きのキーは、`profile` においてキーを押下する手と反対の手のものを利用する
+    fn build_sequences_with(
+        layout: &[KeyAssignment],
+        profile: &HandProfile,
+    ) -> HashMap<char, KeySeq> {
         let mut sequences = HashMap::new();
         let linear_layout = linear::linear_layout();
 
         for (idx, assignment) in layout.iter().enumerate() {
             if let KeyAssignment::A(k) = assignment {
                 let p = linear_layout[idx];
-                let unshift = KeySeq::from_unshift(k.unshift(), &p);
-                let shifted = KeySeq::from_shift(k.shifted(), &p);
-                sequences.insert(k.unshift(), unshift);
-                sequences.insert(k.shifted(), shifted);
+                // 文字を割り当てていない面は入力できる文字がないため、含めない
+                if k.unshift_def().is_some() {
+                    sequences.insert(k.unshift(), KeySeq::from_unshift(k.unshift(), &p));
+                }
+                if k.shifted_def().is_some() {
+                    sequences.insert(k.shifted(), KeySeq::from_shift(k.shifted(), &p, profile));
+                }
 
                 if let Some(turbid) = k.turbid() {
-                    let turbid_pos = match linear::get_hand_of_point(&p) {
-                        crate::layout::Hand::Right => linear_layout[LINEAR_L_TURBID_INDEX],
-                        crate::layout::Hand::Left => linear_layout[LINEAR_R_TURBID_INDEX],
+                    let turbid_pos = match profile.assignment(&p).hand {
+                        crate::layout::Hand::Right => linear_layout[l_turbid_index()],
+                        crate::layout::Hand::Left => linear_layout[r_turbid_index()],
                     };
                     let turbid_seq = KeySeq::from_shift_like(turbid, &p, &turbid_pos);
                     sequences.insert(turbid, turbid_seq);
                 }
 
                 if let Some(semiturbid) = k.semiturbid() {
-                    let semiturbid_pos = match linear::get_hand_of_point(&p) {
-                        crate::layout::Hand::Right => linear_layout[LINEAR_L_SEMITURBID_INDEX],
-                        crate::layout::Hand::Left => linear_layout[LINEAR_R_SEMITURBID_INDEX],
+                    let semiturbid_pos = match profile.assignment(&p).hand {
+                        crate::layout::Hand::Right => linear_layout[l_semiturbid_index()],
+                        crate::layout::Hand::Left => linear_layout[r_semiturbid_index()],
                     };
                     let semiturbid_seq = KeySeq::from_shift_like(semiturbid, &p, &semiturbid_pos);
                     sequences.insert(semiturbid, semiturbid_seq);
                 }
 
                 if let Some(small) = k.small() {
-                    let small_pos = match linear::get_hand_of_point(&p) {
+                    let small_pos = match profile.assignment(&p).hand {
                         crate::layout::Hand::Right => get_left_small_shifter(),
                         crate::layout::Hand::Left => get_right_small_shifter(),
                     };
@@ -462,13 +521,13 @@ impl Keymap {
             let reading_pos = linear::reading_point_points();
             sequences.insert(
                 '、',
-                KeySeq::from_shift_like('。', &reading_pos[0], &reading_pos[1]),
+                KeySeq::from_shift_like('、', &reading_pos[0], &reading_pos[1]),
             );
 
             let punctuation_pos = linear::punctuation_mark_points();
             sequences.insert(
                 '。',
-                KeySeq::from_shift_like('、', &punctuation_pos[0], &punctuation_pos[1]),
+                KeySeq::from_shift_like('。', &punctuation_pos[0], &punctuation_pos[1]),
             );
             let point = linear::turbid_u_point();
             sequences.insert('ゔ', KeySeq::from_unshift('ゔ', &point));
@@ -677,9 +736,18 @@ impl Keymap {
 
     /// [self]から[other]を比較して、異なる文字を取得する
     ///
-    /// 概念上は、self - otherの差集合をキー毎に取得していることと概ね一致する
+    /// 概念上は、self - otherの差集合をキー毎に取得していることと概ね一致する。
+    /// 同じキーの中で無シフト面とシフト面が入れ替わった文字も、打鍵が変わるため差分に含める
     pub fn diff(&self, other: &Keymap) -> HashSet<char> {
         let mut diff = HashSet::new();
+        // 文字と、その文字が配置されている面の組を返す。濁音などは面によらない
+        let layered_chars = |k: &KeyDef| -> HashSet<(usize, char)> {
+            k.chars()
+                .into_iter()
+                .enumerate()
+                .map(|(idx, c)| (idx.min(2), c))
+                .collect()
+        };
 
         for idx in 0..self.layout.len() {
             let key1 = &self.layout[idx];
@@ -687,11 +755,11 @@ impl Keymap {
 
             match (key1, key2) {
                 (KeyAssignment::A(k1), KeyAssignment::A(k2)) => {
-                    let chars1: HashSet<char> = HashSet::from_iter(k1.chars().into_iter());
-                    let chars2: HashSet<char> = HashSet::from_iter(k2.chars().into_iter());
+                    let chars1 = layered_chars(k1);
+                    let chars2 = layered_chars(k2);
 
-                    let diff1 = chars1.difference(&chars2);
-                    let diff2 = chars2.difference(&chars1);
+                    let diff1 = chars1.difference(&chars2).map(|(_, c)| *c);
+                    let diff2 = chars2.difference(&chars1).map(|(_, c)| *c);
                     diff.extend(diff1);
                     diff.extend(diff2)
                 }
@@ -712,27 +780,19 @@ impl Keymap {
         self.sequences.get(&char).cloned()
     }
 
-    /// https://github.com/mobitan/chutoro/tree/main/tools
-    /// 上記での評価用にpairを生成する。生成されるkeymapは、qwerty配列である
-    ///
-    /// # Arguments
-    /// * `key_layout` - マッピング対象のalphabetキー
-    ///
-    /// # Returns
-    /// 最初のセルにひらがな、２番めのセルにkeyのcombinationを返す
-    pub fn key_combinations(&self) -> Vec<(String, String)> {
-        let mut ret: Vec<(String, String)> = Vec::new();
-
-        for (_, seq) in self.sequences.iter() {
-            ret.push((seq.char().to_string(), seq.to_char_sequence()))
-        }
-
-        ret
+    /// 入力できるすべての文字のキーを、文字の順序で返す
+    pub fn sequences(&self) -> Vec<&KeySeq> {
+        let mut sequences = self.sequences.values().collect::<Vec<_>>();
+        sequences.sort_by_key(|v| v.char());
+        sequences
     }
 
     fn format_keymap(&self, layout: &[Option<char>]) -> String {
         let layout_mapping = linear::linear_layout();
-        let header: String = (0..9)
+        let keys = layout::current().keys();
+        let rows = keys.iter().map(|v| v.row).max().unwrap_or(0) + 1;
+        let cols = keys.iter().map(|v| v.col).max().unwrap_or(0) + 1;
+        let header: String = (0..cols - 1)
             .map(|_| "┳".to_string())
             .collect::<Vec<_>>()
             .join("━");
@@ -741,13 +801,13 @@ impl Keymap {
         let separator = format!(
             "{}{}{}\n",
             "┣━",
-            (0..9)
+            (0..cols - 1)
                 .map(|_| { "╋".to_string() })
                 .collect::<Vec<String>>()
                 .join("━"),
             "━┫"
         );
-        let mut square_layout = vec![vec![None; 10]; 3];
+        let mut square_layout = vec![vec![None; cols]; rows];
         for (idx, ch) in layout.iter().enumerate() {
             let (r, c): (usize, usize) = layout_mapping[idx].into();
 
@@ -770,7 +830,7 @@ impl Keymap {
         let footer = format!(
             "{}{}{}",
             "┗━",
-            (0..9)
+            (0..cols - 1)
                 .map(|_| "┻".to_string())
                 .collect::<Vec<_>>()
                 .join("━"),
@@ -879,3 +939,82 @@ semiturbid\n{}\n
         )
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        connection_score::ConnectionScore, frequency_table::FrequencyTable, layout::Hand,
+        penalty::RuleSet, timing::TwoKeyTiming,
+    };
+
+    use super::*;
+
+    /// テスト用に、制約を満たすkeymapを生成する
+    pub(crate) fn generate_keymap(rng: &mut StdRng, table: &FrequencyTable) -> Keymap {
+        loop {
+            let mut assigner = KeyAssigner::from_freq(table, &HashMap::new());
+
+            if let Some(keymap) = Keymap::generate(rng, &mut assigner) {
+                return keymap;
+            }
+        }
+    }
+
+    #[test]
+    fn serialize_only_layout() {
+        // arrange
+        let keymap = generate_keymap(
+            &mut rand::SeedableRng::seed_from_u64(1),
+            &FrequencyTable::new(),
+        );
+
+        // act
+        let bin = postcard::to_allocvec(&keymap).unwrap();
+        let ret: Keymap = postcard::from_bytes(&bin).unwrap();
+
+        // assert
+        assert_eq!(ret, keymap);
+    }
+
+    #[test]
+    fn shifter_follows_hand_profile() {
+        // arrange
+        let keymap = generate_keymap(
+            &mut rand::SeedableRng::seed_from_u64(1),
+            &FrequencyTable::new(),
+        );
+        let layout = layout::current();
+        let profile = HandProfile::load("b-right-index", layout).unwrap();
+        let scores = ConnectionScore::new(
+            &TwoKeyTiming {
+                timings: HashMap::new(),
+            },
+            &profile,
+            &RuleSet::builtin(layout),
+        );
+        let b = layout.key_of_label('b').unwrap().point();
+
+        // act
+        let standard = Keymap::build_sequences_with(&keymap.layout, &HandProfile::standard(layout));
+        let sequences = Keymap::build_sequences_with(&keymap.layout, &profile);
+
+        // assert
+        let shifted = sequences
+            .iter()
+            .filter(|(_, seq)| {
+                let strokes = seq.strokes();
+                strokes.len() == 2 && strokes[1].point == b
+            })
+            .collect::<Vec<_>>();
+        assert!(!shifted.is_empty(), "should have shifted chars on b");
+        for (c, seq) in shifted {
+            // bは右手で押下するため、シフト系のキーは左手のものを利用する
+            let strokes = seq.strokes();
+            assert_eq!(profile.assignment(&strokes[0].point).hand, Hand::Left);
+            assert_ne!(standard[c], *seq, "{} should not follow the layout", c);
+            assert_eq!(scores.explain(&[&scores.key_strokes(&strokes)]).shift, 0);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    char_def::{self, CharDef},
+    key_def::KeyDef,
+    keymap::Keymap,
+    layout::linear,
+};
+
+/// 現在のファイル形式のversion
+const VERSION: u32 = 1;
+
+/// キーマップを保存するファイルの形式。
+///
+/// 人が編集できるよう、QWERTYでのキーと、そのキーの無シフト面・シフト面の文字のみを記録する。
+/// 濁音などは、記録した文字から導出される。
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "keys": [
+///     { "key": "w", "unshift": "の", "shift": "ぬ" },
+///     ...
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+    version: u32,
+    keys: Vec<KeyEntry>,
+}
+
+/// 1キー分の定義
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+    /// QWERTYでのキー
+    key: char,
+    /// 無シフト面の文字
+    unshift: Option<char>,
+    /// シフト面の文字
+    shift: Option<char>,
+}
+
+impl From<&Keymap> for KeymapFile {
+    fn from(keymap: &Keymap) -> Self {
+        let keys = linear::linear_layout()
+            .iter()
+            .zip(keymap.iter())
+            .map(|(point, def)| KeyEntry {
+                key: linear::get_char_of_point(point),
+                unshift: def.unshift_def().map(|v| v.normal()),
+                shift: def.shifted_def().map(|v| v.normal()),
+            })
+            .collect();
+
+        KeymapFile {
+            version: VERSION,
+            keys,
+        }
+    }
+}
+
+impl TryFrom<KeymapFile> for Keymap {
+    type Error = anyhow::Error;
+
+    fn try_from(file: KeymapFile) -> Result<Self, Self::Error> {
+        if file.version != VERSION {
+            anyhow::bail!("unsupported version {}", file.version);
+        }
+
+        let layout = linear::linear_layout();
+        let mapping = linear::linear_mapping();
+        let mut defs: Vec<Option<KeyDef>> = vec![None; layout.len()];
+
+        for entry in file.keys {
+            let index = mapping
+                .get(&entry.key)
+                .and_then(|p| layout.iter().position(|v| v == p))
+                .ok_or_else(|| anyhow::anyhow!("key '{}': not in the layout", entry.key))?;
+
+            if defs[index].is_some() {
+                anyhow::bail!("key '{}': defined more than once", entry.key);
+            }
+
+            let find = |c: Option<char>| -> anyhow::Result<Option<CharDef>> {
+                c.map(|c| {
+                    char_def::find(c).ok_or_else(|| {
+                        anyhow::anyhow!("key '{}': '{}' is not assignable", entry.key, c)
+                    })
+                })
+                .transpose()
+            };
+            defs[index] = Some(KeyDef::new(find(entry.unshift)?, find(entry.shift)?));
+        }
+
+        let defs = defs
+            .into_iter()
+            .zip(layout.iter())
+            .map(|(def, point)| {
+                def.ok_or_else(|| {
+                    anyhow::anyhow!("key '{}': missing", linear::get_char_of_point(point))
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Keymap::from_key_defs(defs)
+    }
+}
+
+/// `keymap` をJSONとして `path` に保存する
+pub fn save_keymap(path: &Path, keymap: &Keymap) -> anyhow::Result<()> {
+    let json = serde_json::to_string_pretty(&KeymapFile::from(keymap))?;
+    fs::write(path, json + "\n")
+        .with_context(|| format!("can not write keymap {}", path.display()))?;
+    Ok(())
+}
+
+/// `path` からキーマップを読み込む
+///
+/// 読み込んだキーマップは、[Keymap::meet_requirements]を満たしていることを確認する
+pub fn load_keymap(path: &Path) -> anyhow::Result<Keymap> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("can not read keymap {}", path.display()))?;
+
+    parse_keymap(&json).with_context(|| format!("invalid keymap {}", path.display()))
+}
+
+/// JSONの文字列からキーマップを読み込む
+fn parse_keymap(json: &str) -> anyhow::Result<Keymap> {
+    let file: KeymapFile = serde_json::from_str(json)?;
+    Keymap::try_from(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{frequency_table::FrequencyTable, keymap::tests::generate_keymap};
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        // arrange
+        let keymap = generate_keymap(&mut StdRng::seed_from_u64(1), &FrequencyTable::new());
+
+        // act
+        let json = serde_json::to_string_pretty(&KeymapFile::from(&keymap)).unwrap();
+        let ret = parse_keymap(&json).unwrap();
+
+        // assert
+        assert_eq!(ret, keymap);
+    }
+
+    #[test]
+    fn reject_keymap_not_meeting_requirements() {
+        // arrange
+        let keymap = generate_keymap(&mut StdRng::seed_from_u64(1), &FrequencyTable::new());
+        let mut file = KeymapFile::from(&keymap);
+        let shift = file.keys.iter().position(|v| v.key == 'd').unwrap();
+        file.keys[shift].shift = None;
+
+        // act
+        let ret = Keymap::try_from(file);
+
+        // assert
+        assert!(ret.is_err(), "should not be valid keymap");
+    }
+
+    #[test]
+    fn reject_missing_key() {
+        // arrange
+        let keymap = generate_keymap(&mut StdRng::seed_from_u64(1), &FrequencyTable::new());
+        let mut file = KeymapFile::from(&keymap);
+        file.keys.retain(|v| v.key != 'a');
+
+        // act
+        let ret = Keymap::try_from(file);
+
+        // assert
+        let message = format!("{:#}", ret.unwrap_err());
+        assert!(message.contains("'a'"), "should point the key: {message}");
+    }
+}
@@ -1,3 +1,13 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::OnceLock,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
 /// layoutにおける位置を表す
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Point(usize, usize);
@@ -36,121 +46,396 @@ impl From<(usize, usize)> for Point {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Hand {
     Right,
     Left,
 }
 
+/// キーを押下する指
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Finger {
+    Thumb,
+    Index,
+    Middle,
+    Ring,
+    Pinky,
+}
+
+/// 物理的なキーの定義
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Key {
+    /// QWERTYにおいて対応する文字
+    pub label: char,
+    pub row: usize,
+    pub col: usize,
+    pub hand: Hand,
+    pub finger: Finger,
+    /// キーを押下する指の負荷
+    pub weight: u16,
+    /// 文字を割り当てるキーかどうか。falseの場合は特殊なキーとしてのみ利用する
+    #[serde(default = "assignable_by_default")]
+    pub assignable: bool,
+}
+
+fn assignable_by_default() -> bool {
+    true
+}
+
+impl Key {
+    /// キーの位置を返す
+    pub fn point(&self) -> Point {
+        Point(self.row, self.col)
+    }
+}
+
+/// 特殊な役割を持つキー。いずれもキーのlabelで指定する
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Roles {
+    pub left_shift: char,
+    pub right_shift: char,
+    pub left_turbid: char,
+    pub right_turbid: char,
+    pub left_semiturbid: char,
+    pub right_semiturbid: char,
+    pub left_small: char,
+    pub right_small: char,
+    /// 読点を入力する2キー
+    pub reading_point: [char; 2],
+    /// 句点を入力する2キー
+    pub punctuation_mark: [char; 2],
+    /// ゔを入力するキー
+    pub turbid_u: char,
+}
+
+/// [Roles]のキーを、位置に解決したもの。
+///
+/// キーマップを構築するたびに参照するため、レイアウトを読み込んだ時点で一度だけ解決しておく
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RolePoints {
+    /// シフト・濁音・半濁音のキーの、[Layout::assignable]におけるindex
+    left_shift: usize,
+    right_shift: usize,
+    left_turbid: usize,
+    right_turbid: usize,
+    left_semiturbid: usize,
+    right_semiturbid: usize,
+    left_small: Point,
+    right_small: Point,
+    reading_point: [Point; 2],
+    punctuation_mark: [Point; 2],
+    turbid_u: Point,
+}
+
+/// レイアウトファイルの内容
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LayoutDefinition {
+    keys: Vec<Key>,
+    #[serde(default)]
+    arpeggios: Vec<[char; 2]>,
+    roles: Roles,
+}
+
+/// 物理的なキーの配置と、それぞれのキーの役割。
+///
+/// レイアウトファイルはTOMLで記述する。記述例は `layouts/qwerty.toml` を参照。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "LayoutDefinition", try_from = "LayoutDefinition")]
+pub struct Layout {
+    definition: LayoutDefinition,
+    /// 文字を割り当てるキーの位置
+    assignable: Vec<Point>,
+    /// 位置から[Key]へのmapping
+    keys_by_point: HashMap<Point, usize>,
+    /// labelから[Key]へのmapping
+    keys_by_label: HashMap<char, usize>,
+    arpeggios: HashSet<(Point, Point)>,
+    role_points: RolePoints,
+}
+
+impl From<Layout> for LayoutDefinition {
+    fn from(value: Layout) -> Self {
+        value.definition
+    }
+}
+
+impl TryFrom<LayoutDefinition> for Layout {
+    type Error = anyhow::Error;
+
+    fn try_from(definition: LayoutDefinition) -> Result<Self, Self::Error> {
+        let mut keys_by_point = HashMap::new();
+        let mut keys_by_label = HashMap::new();
+
+        for (idx, key) in definition.keys.iter().enumerate() {
+            if keys_by_label.insert(key.label, idx).is_some() {
+                anyhow::bail!("key '{}': defined more than once", key.label);
+            }
+            if keys_by_point.insert(key.point(), idx).is_some() {
+                anyhow::bail!(
+                    "key '{}': position ({}, {}) is already used",
+                    key.label,
+                    key.row,
+                    key.col
+                );
+            }
+        }
+
+        let find = |role: &str, label: char| -> anyhow::Result<&Key> {
+            keys_by_label
+                .get(&label)
+                .map(|idx| &definition.keys[*idx])
+                .ok_or_else(|| anyhow::anyhow!("roles.{}: key '{}' is not defined", role, label))
+        };
+
+        let roles = &definition.roles;
+        for (role, label, hand) in [
+            ("left_shift", roles.left_shift, Hand::Left),
+            ("right_shift", roles.right_shift, Hand::Right),
+            ("left_turbid", roles.left_turbid, Hand::Left),
+            ("right_turbid", roles.right_turbid, Hand::Right),
+            ("left_semiturbid", roles.left_semiturbid, Hand::Left),
+            ("right_semiturbid", roles.right_semiturbid, Hand::Right),
+        ] {
+            let key = find(role, label)?;
+            if !key.assignable {
+                anyhow::bail!("roles.{}: key '{}' should be assignable", role, label);
+            }
+            if key.hand != hand {
+                anyhow::bail!("roles.{}: key '{}' is on the other hand", role, label);
+            }
+        }
+
+        let shifters = [
+            roles.left_shift,
+            roles.right_shift,
+            roles.left_turbid,
+            roles.right_turbid,
+            roles.left_semiturbid,
+            roles.right_semiturbid,
+        ];
+        if shifters.iter().collect::<HashSet<_>>().len() != shifters.len() {
+            anyhow::bail!("roles: shift, turbid and semiturbid keys should be different");
+        }
+
+        for (role, label, hand) in [
+            ("left_small", roles.left_small, Hand::Left),
+            ("right_small", roles.right_small, Hand::Right),
+        ] {
+            if find(role, label)?.hand != hand {
+                anyhow::bail!("roles.{}: key '{}' is on the other hand", role, label);
+            }
+        }
+
+        for label in roles.reading_point.iter() {
+            find("reading_point", *label)?;
+        }
+        for label in roles.punctuation_mark.iter() {
+            find("punctuation_mark", *label)?;
+        }
+        find("turbid_u", roles.turbid_u)?;
+
+        let mut arpeggios = HashSet::new();
+        for [first, second] in definition.arpeggios.iter() {
+            let first = find("arpeggios", *first)?.point();
+            let second = find("arpeggios", *second)?.point();
+            arpeggios.insert((first, second));
+            arpeggios.insert((second, first));
+        }
+
+        let assignable = definition
+            .keys
+            .iter()
+            .filter(|v| v.assignable)
+            .map(|v| v.point())
+            .collect::<Vec<_>>();
+
+        let point_of = |label: char| definition.keys[keys_by_label[&label]].point();
+        let index_of = |label: char| {
+            let point = point_of(label);
+            assignable
+                .iter()
+                .position(|v| *v == point)
+                .expect("role should be validated")
+        };
+        let role_points = RolePoints {
+            left_shift: index_of(roles.left_shift),
+            right_shift: index_of(roles.right_shift),
+            left_turbid: index_of(roles.left_turbid),
+            right_turbid: index_of(roles.right_turbid),
+            left_semiturbid: index_of(roles.left_semiturbid),
+            right_semiturbid: index_of(roles.right_semiturbid),
+            left_small: point_of(roles.left_small),
+            right_small: point_of(roles.right_small),
+            reading_point: roles.reading_point.map(point_of),
+            punctuation_mark: roles.punctuation_mark.map(point_of),
+            turbid_u: point_of(roles.turbid_u),
+        };
+
+        Ok(Layout {
+            definition,
+            assignable,
+            keys_by_point,
+            keys_by_label,
+            arpeggios,
+            role_points,
+        })
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::parse(include_str!("../layouts/qwerty.toml"))
+            .expect("builtin layout should be valid")
+    }
+}
+
+impl Layout {
+    /// `path` からレイアウトを読み込む
+    pub fn load(path: &Path) -> anyhow::Result<Layout> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("can not read layout {}", path.display()))?;
+
+        Layout::parse(&text).with_context(|| format!("invalid layout {}", path.display()))
+    }
+
+    /// TOMLの文字列からレイアウトを読み込む
+    pub fn parse(text: &str) -> anyhow::Result<Layout> {
+        let definition: LayoutDefinition = toml::from_str(text)?;
+        Layout::try_from(definition)
+    }
+
+    /// 定義されたすべてのキーを返す
+    pub fn keys(&self) -> &[Key] {
+        &self.definition.keys
+    }
+
+    /// 特殊な役割を持つキーを返す
+    pub fn roles(&self) -> &Roles {
+        &self.definition.roles
+    }
+
+    /// 指定された位置のキーを返す
+    pub fn key_of_point(&self, point: &Point) -> Option<&Key> {
+        self.keys_by_point
+            .get(point)
+            .map(|idx| &self.definition.keys[*idx])
+    }
+
+    /// 指定されたlabelのキーを返す
+    pub fn key_of_label(&self, label: char) -> Option<&Key> {
+        self.keys_by_label
+            .get(&label)
+            .map(|idx| &self.definition.keys[*idx])
+    }
+
+    /// 2キーが押下しやすい組み合わせかどうかを返す
+    pub fn is_arpeggio(&self, first: &Point, second: &Point) -> bool {
+        self.arpeggios.contains(&(*first, *second))
+    }
+}
+
+static CURRENT: OnceLock<Layout> = OnceLock::new();
+
+/// プロセス全体で利用するレイアウトを設定する。
+///
+/// レイアウトは一度しか設定できないため、[current]が呼ばれる前に設定する必要がある
+pub fn install(layout: Layout) -> anyhow::Result<()> {
+    let installed = CURRENT.get_or_init(|| layout.clone());
+
+    if *installed != layout {
+        anyhow::bail!("another layout is already in use");
+    }
+    Ok(())
+}
+
+/// 現在のレイアウトを返す。設定されていない場合は組み込みのレイアウトを利用する
+pub fn current() -> &'static Layout {
+    CURRENT.get_or_init(Layout::default)
+}
+
 /// 直線的なレイアウトを表す。ここでのレイアウトは、あくまでも通常のキー配置との対応関係のみを管理しており、
 /// 割当などは対応外である。
+///
+/// いずれも[current]のレイアウトに従う
 pub mod linear {
     use std::collections::HashMap;
 
-    use super::{Hand, Point};
-
-    const LINEAR_MAPPING: [(char, Point); 26] = [
-        // ('q', Point { row: 0, col: 0 }),
-        ('w', Point(0, 1)),
-        ('e', Point(0, 2)),
-        ('r', Point(0, 3)),
-        // ('t', Point { row: 0, col: 4 }),
-        // ('y', Point { row: 0, col: 5 }),
-        ('u', Point(0, 6)),
-        ('i', Point(0, 7)),
-        ('o', Point(0, 8)),
-        // ('p', Point { row: 0, col: 8 }),
-        ('a', Point(1, 0)),
-        ('s', Point(1, 1)),
-        ('d', Point(1, 2)),
-        ('f', Point(1, 3)),
-        ('g', Point(1, 4)),
-        ('h', Point(1, 5)),
-        ('j', Point(1, 6)),
-        ('k', Point(1, 7)),
-        ('l', Point(1, 8)),
-        (';', Point(1, 9)),
-        ('z', Point(2, 0)),
-        ('x', Point(2, 1)),
-        ('c', Point(2, 2)),
-        ('v', Point(2, 3)),
-        ('b', Point(2, 4)),
-        ('n', Point(2, 5)),
-        ('m', Point(2, 6)),
-        (',', Point(2, 7)),
-        ('.', Point(2, 8)),
-        ('/', Point(2, 9)),
-    ];
+    use super::{current, Point};
 
     /// 各特殊キーの位置
-    pub const LINEAR_L_SHIFT_INDEX: usize = 8;
-    pub const LINEAR_R_SHIFT_INDEX: usize = 13;
-    pub const LINEAR_L_TURBID_INDEX: usize = 9;
-    pub const LINEAR_R_TURBID_INDEX: usize = 12;
-    pub const LINEAR_L_SEMITURBID_INDEX: usize = 19;
-    pub const LINEAR_R_SEMITURBID_INDEX: usize = 22;
+    pub fn l_shift_index() -> usize {
+        current().role_points.left_shift
+    }
+
+    pub fn r_shift_index() -> usize {
+        current().role_points.right_shift
+    }
+
+    pub fn l_turbid_index() -> usize {
+        current().role_points.left_turbid
+    }
+
+    pub fn r_turbid_index() -> usize {
+        current().role_points.right_turbid
+    }
+
+    pub fn l_semiturbid_index() -> usize {
+        current().role_points.left_semiturbid
+    }
+
+    pub fn r_semiturbid_index() -> usize {
+        current().role_points.right_semiturbid
+    }
 
     /// 読点の位置
-    pub fn reading_point_points() -> Vec<Point> {
-        vec![Point(1, 6), Point(1, 7)]
+    pub fn reading_point_points() -> [Point; 2] {
+        current().role_points.reading_point
     }
 
     /// 句点の位置
-    pub fn punctuation_mark_points() -> Vec<Point> {
-        vec![Point(1, 2), Point(1, 3)]
+    pub fn punctuation_mark_points() -> [Point; 2] {
+        current().role_points.punctuation_mark
     }
 
     /// ゔの位置
     pub fn turbid_u_point() -> Point {
-        Point(0, 0)
+        current().role_points.turbid_u
     }
 
     /// 直線的になるレイアウトを返す
-    pub fn linear_layout() -> Vec<Point> {
-        LINEAR_MAPPING
-            .iter()
-            .cloned()
-            .map(|v| v.1)
-            .collect::<Vec<_>>()
+    pub fn linear_layout() -> &'static [Point] {
+        &current().assignable
     }
 
     /// 直線的になるレイアウトと、QWERTYにおいて対応する文字のmappingを返す
     pub fn linear_mapping() -> HashMap<char, Point> {
-        LINEAR_MAPPING.iter().cloned().collect()
-    }
-
-    /// layoutにおいて担当する手を返す
-    pub fn get_hand_of_point(point: &Point) -> Hand {
-        if point.col() <= 4 {
-            Hand::Left
-        } else {
-            Hand::Right
-        }
+        current()
+            .keys()
+            .iter()
+            .filter(|v| v.assignable)
+            .map(|v| (v.label, v.point()))
+            .collect()
     }
 
     pub fn get_left_small_shifter() -> Point {
-        Point(0, 0)
+        current().role_points.left_small
     }
 
     pub fn get_right_small_shifter() -> Point {
-        Point(0, 9)
+        current().role_points.right_small
     }
 
     /// layoutにおいて、指定された位置に対応する文字を返す
     pub fn get_char_of_point(point: &Point) -> char {
-        if *point == get_left_small_shifter() {
-            return 'q';
-        }
-
-        if *point == get_right_small_shifter() {
-            return 'p';
-        }
-
-        LINEAR_MAPPING
-            .iter()
-            .find(|(_, p)| *p == *point)
-            .map(|(c, _)| *c)
-            .unwrap()
+        current()
+            .key_of_point(point)
+            .map(|v| v.label)
+            .expect("point should be in the layout")
     }
 }
 
@@ -158,8 +443,6 @@ pub mod linear {
 mod tests {
     use tests::linear::get_char_of_point;
 
-    use self::linear::get_hand_of_point;
-
     use super::*;
 
     #[test]
@@ -167,10 +450,10 @@ mod tests {
         // arrange
 
         // act
-        let ret = get_hand_of_point(&Point(1, 3));
+        let ret = current().key_of_point(&Point(1, 3)).map(|v| v.hand);
 
         // assert
-        assert_eq!(ret, Hand::Left);
+        assert_eq!(ret, Some(Hand::Left));
     }
 
     #[test]
@@ -178,10 +461,10 @@ mod tests {
         // arrange
 
         // act
-        let ret = get_hand_of_point(&Point(1, 5));
+        let ret = current().key_of_point(&Point(1, 5)).map(|v| v.hand);
 
         // assert
-        assert_eq!(ret, Hand::Right);
+        assert_eq!(ret, Some(Hand::Right));
     }
 
     #[test]
@@ -194,4 +477,50 @@ mod tests {
         // assert
         assert_eq!(ret, 'h');
     }
+
+    #[test]
+    fn builtin_layout_keeps_special_keys() {
+        // arrange
+
+        // act
+        let layout = linear::linear_layout();
+
+        // assert
+        assert_eq!(layout.len(), 26);
+        assert_eq!(linear::l_shift_index(), 8);
+        assert_eq!(linear::r_shift_index(), 13);
+        assert_eq!(linear::l_turbid_index(), 9);
+        assert_eq!(linear::r_turbid_index(), 12);
+        assert_eq!(linear::l_semiturbid_index(), 19);
+        assert_eq!(linear::r_semiturbid_index(), 22);
+    }
+
+    #[test]
+    fn parse_number_row_layout() {
+        // arrange
+
+        // act
+        let layout = Layout::parse(include_str!("../layouts/qwerty-number-row.toml")).unwrap();
+
+        // assert
+        assert!(layout.key_of_label('t').is_some_and(|v| v.assignable));
+        assert!(layout.key_of_label('1').is_some_and(|v| v.assignable));
+    }
+
+    #[test]
+    fn reject_role_on_other_hand() {
+        // arrange
+        let text = include_str!("../layouts/qwerty.toml")
+            .replace("left_shift = \"d\"", "left_shift = \"l\"");
+
+        // act
+        let ret = Layout::parse(&text);
+
+        // assert
+        let message = format!("{:#}", ret.unwrap_err());
+        assert!(
+            message.contains("roles.left_shift"),
+            "should point the role: {message}"
+        );
+    }
 }
@@ -1,6 +1,5 @@
 use std::{
-    collections::HashMap,
-    env::args,
+    collections::BTreeMap,
     fs::{self, File},
     io::{Read, Write},
     path::Path,
@@ -11,79 +10,179 @@ use std::{
     time::SystemTime,
 };
 
+use anyhow::Context;
+use checkpoint::{Checkpoint, Scoring};
+use clap::Parser;
+#[cfg(unix)]
+use cli::MeasureTimingArgs;
+use cli::{
+    AggregateTimingArgs, Cli, Command, CompareArgs, ConvertTimingArgs, CorpusArgs, CountNgramsArgs,
+    EvaluateArgs, ExportArgs, ExportFormat, InspectTableArgs, MergeNgramsArgs, OptimizeArgs,
+    TimingAggregation, TimingArgs,
+};
+use config::Config;
 use frequency_table::FrequencyTable;
 use keymap::Keymap;
+use keymap_file::{load_keymap, save_keymap};
 use postcard::{from_bytes, to_allocvec};
-use rand::{random, rngs::StdRng, SeedableRng};
-use score::Conjunction;
+use rand::{random, rngs::StdRng, Rng, SeedableRng};
+use score::{Conjunction, Corpus};
 
 use crate::{
-    connection_score::{ConnectionScore, TwoKeyTiming},
+    char_def::Inventory,
+    connection_score::ConnectionScore,
+    layout::{linear, Layout},
+    penalty::RuleSet,
     playground::Playground,
+    timing::{AggregatedTiming, Aggregation, TwoKeyTiming},
 };
 
 mod char_def;
+mod checkpoint;
+mod cli;
+mod config;
 mod connection_score;
+mod export;
 mod frequency_layer;
 mod frequency_table;
+mod hand_profile;
 mod key_def;
 mod key_seq;
 mod keymap;
+mod keymap_file;
 mod layout;
+#[cfg(unix)]
+mod measure;
+mod ngram;
+mod penalty;
 mod playground;
 mod score;
+mod timing;
+
+/// n-gramと出現回数をタブ区切りで記録したファイルから、連接を読み込む。
+///
+/// n-gramの長さは任意であり、異なる長さのn-gramが混在していても良い
+fn read_ngrams(args: &CorpusArgs) -> anyhow::Result<Vec<Conjunction>> {
+    let path = &args.corpus;
+    let file =
+        File::open(path).with_context(|| format!("can not read corpus {}", path.display()))?;
+    let (conjunctions, stats) = ngram::read_conjunctions(file, args.split_unknown)
+        .with_context(|| format!("invalid corpus {}", path.display()))?;
+
+    let mut lengths: BTreeMap<usize, usize> = BTreeMap::new();
+    for conjunction in conjunctions.iter() {
+        *lengths.entry(conjunction.text.len()).or_default() += 1;
+    }
+    log::info!(
+        "log load {} n-grams as conjunction ({})",
+        conjunctions.len(),
+        lengths
+            .iter()
+            .map(|(len, count)| format!("{}-gram: {}", len, count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let unknown_chars = stats
+        .unknown_chars()
+        .iter()
+        .take(10)
+        .map(|(c, count)| format!("{}: {}", c, count))
+        .collect::<Vec<_>>();
+    if args.split_unknown {
+        log::info!(
+            "split {} of {} n-grams at unknown characters ({})",
+            stats.split_ngrams,
+            stats.ngrams,
+            unknown_chars.join(", ")
+        );
+    } else if stats.dropped_ngrams > 0 {
+        log::log!(
+            if stats.dropped_ratio() >= 0.05 {
+                log::Level::Warn
+            } else {
+                log::Level::Info
+            },
+            "dropped {} of {} n-grams, {:.1}% of appearances, with unknown characters ({}). --split-unknown keeps the rest of them",
+            stats.dropped_ngrams,
+            stats.ngrams,
+            stats.dropped_ratio() * 100.0,
+            unknown_chars.join(", ")
+        );
+    }
 
-fn read_4gram(path: &Path) -> anyhow::Result<Vec<Conjunction>> {
-    let mut conjunctions = Vec::new();
-    let file = File::open(path).unwrap();
+    Ok(conjunctions)
+}
 
-    let mut rdr = csv::ReaderBuilder::new()
-        .delimiter(b'\t')
-        .from_reader(&file);
+/// 打鍵時間を全て読み込み、集約する
+fn aggregate_timings(args: &TimingArgs) -> anyhow::Result<AggregatedTiming> {
+    if !args.weights.is_empty() && args.weights.len() != args.timings.len() {
+        anyhow::bail!(
+            "{} timing weights are given for {} timings",
+            args.weights.len(),
+            args.timings.len()
+        );
+    }
+    if args.weights.iter().any(|v| !v.is_finite() || *v < 0.0) {
+        anyhow::bail!("timing weights should not be negative");
+    }
+    if !(0.0..0.5).contains(&args.trim_ratio) {
+        anyhow::bail!("trim ratio should be in [0, 0.5)");
+    }
 
-    let char_position_map: HashMap<char, (u64, usize)> = char_def::all_chars()
-        .into_iter()
+    let sources = args
+        .timings
+        .iter()
         .enumerate()
-        .map(|(idx, v)| (v.1, (v.0, idx)))
-        .collect();
-
-    for result in rdr.records() {
-        // The iterator yields Result<StringRecord, Error>, so we check the
-        // error here.
-        let record = result?;
-        let text: String = record.get(0).unwrap().to_string();
-        let appearances: u32 = record.get(1).unwrap().parse()?;
-
-        let filtered = text
-            .chars()
-            .filter_map(|v| char_position_map.get(&v))
-            .cloned()
-            .collect::<Vec<_>>();
-        if filtered.len() != text.chars().collect::<Vec<_>>().len() {
-            continue;
-        }
+        .map(|(idx, path)| {
+            let records = timing::read_source(path)
+                .with_context(|| format!("failed to load timing {}", path.display()))?;
+            Ok((records, args.weights.get(idx).copied().unwrap_or(1.0)))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let aggregation = match args.timing_aggregation {
+        TimingAggregation::Mean => Aggregation::Mean,
+        TimingAggregation::Median => Aggregation::Median,
+        TimingAggregation::TrimmedMean => Aggregation::TrimmedMean(args.trim_ratio),
+    };
 
-        let hash = filtered
-            .iter()
-            .map(|(v, _)| v)
-            .fold(1, |accum, v| accum * *v);
+    Ok(timing::aggregate(&sources, aggregation))
+}
 
-        conjunctions.push(Conjunction {
-            text: filtered.iter().cloned().map(|v| v.1).collect(),
-            appearances,
-            hash,
-        });
+fn read_timing(args: &TimingArgs) -> anyhow::Result<TwoKeyTiming> {
+    let aggregated = aggregate_timings(args)?;
+    let disagreements = aggregated
+        .disagreements
+        .iter()
+        .filter(|v| v.spread() >= timing::DISAGREEMENT_THRESHOLD)
+        .count();
+    if disagreements > 0 {
+        log::warn!(
+            "{} key pairs differ by {:.0}% or more between timings, see aggregate-timing for details",
+            disagreements,
+            timing::DISAGREEMENT_THRESHOLD * 100.0
+        );
     }
 
-    log::info!("log load {} 4-grams as conjunction", conjunctions.len());
+    let timing = TwoKeyTiming::from_records(&aggregated.records);
+    log::info!("load {} timings", timing.timings.len());
+    Ok(timing)
+}
 
-    Ok(conjunctions)
+/// 打鍵時間と手の使い方、ペナルティのルールから、連接の評価を作成する
+fn connection_score(args: &CorpusArgs) -> anyhow::Result<ConnectionScore> {
+    let timing = read_timing(&args.timing)?;
+    let profile = hand_profile::resolve_current()?;
+    let rules = RuleSet::load(&args.rules, layout::current())?;
+
+    Ok(ConnectionScore::new(&timing, profile, &rules))
 }
 
-fn save_frequency(table: &FrequencyTable) {
-    let mut output = File::create("./frequency_table.bin").unwrap();
-    let bin = to_allocvec(&table).unwrap();
-    output.write_all(&bin).unwrap();
+fn save_frequency(path: &Path, table: &FrequencyTable) -> anyhow::Result<()> {
+    let mut output = File::create(path)?;
+    let bin = to_allocvec(&table)?;
+    output.write_all(&bin)?;
+    Ok(())
 }
 
 fn read_frequency(path: &Path) -> anyhow::Result<FrequencyTable> {
@@ -91,6 +190,16 @@ fn read_frequency(path: &Path) -> anyhow::Result<FrequencyTable> {
     let mut buf = Vec::new();
     input.read_to_end(&mut buf)?;
     let data = from_bytes::<FrequencyTable>(&buf)?;
+    if data.frequencies().len() != linear::linear_layout().len() {
+        anyhow::bail!(
+            "frequency table has {} keys, but the layout has {}",
+            data.frequencies().len(),
+            linear::linear_layout().len()
+        );
+    }
+    if !data.matches_chars() {
+        anyhow::bail!("frequency table was created with another chars");
+    }
     log::info!("frequency loaded");
     Ok(data)
 }
@@ -134,90 +243,464 @@ impl Bench {
 fn main() -> anyhow::Result<()> {
     env_logger::init();
 
-    let path = args().nth(1).expect("missing path");
-    let frequency = args()
-        .nth(2)
-        .and_then(|v| read_frequency(Path::new(&v)).ok())
-        .unwrap_or_default();
-    let mut rng = StdRng::seed_from_u64(random());
+    let cli = Cli::parse();
+    if let Some(path) = &cli.layout {
+        layout::install(Layout::load(path)?)?;
+    }
+    if let Some(name) = &cli.chars {
+        char_def::install(Inventory::load(name)?)?;
+    }
+    if let Some(name) = &cli.hand_profile {
+        hand_profile::select(name)?;
+    }
+
+    match cli.command {
+        Command::Optimize(args) => optimize(&args),
+        Command::Evaluate(args) => evaluate(&args),
+        Command::Export(args) => export(&args),
+        Command::InspectTable(args) => inspect_table(&args),
+        Command::Compare(args) => compare(&args),
+        Command::ConvertTiming(args) => convert_timing(&args),
+        Command::AggregateTiming(args) => aggregate_timing(&args),
+        #[cfg(unix)]
+        Command::MeasureTiming(args) => measure_timing(&args),
+        Command::CountNgrams(args) => count_ngrams(&args),
+        Command::MergeNgrams(args) => merge_ngrams(&args),
+    }
+}
+
+/// 遺伝的アルゴリズムによってkeymapを最適化する
+fn optimize(args: &OptimizeArgs) -> anyhow::Result<()> {
+    let (config, checkpoint) = match &args.resume {
+        Some(path) => {
+            let checkpoint = Checkpoint::load(path)?;
+            (
+                override_config(checkpoint.config.clone(), args)?,
+                Some(checkpoint),
+            )
+        }
+        None => {
+            let config = match &args.config {
+                Some(path) => Config::load(path)?,
+                None => Config::default(),
+            };
+            (override_config(config, args)?, None)
+        }
+    };
+
+    char_def::current().fits(layout::current())?;
+
+    let conjunctions = read_ngrams(&args.data)?;
+    let corpus = Arc::new(Corpus::new(conjunctions, connection_score(&args.data)?));
+    let scoring = Scoring::new(&args.data, &corpus);
+    if let Some(checkpoint) = &checkpoint {
+        checkpoint.scoring.ensure_same(&scoring)?;
+    }
+
+    // 世代ごとに乱数を初期化し、どの世代の境界からでも同じ乱数列で再開できるようにする
+    let (mut playground, mut rng_seed, mut best, mut last_scores) = match checkpoint {
+        Some(checkpoint) => (
+            Playground::restore(&config.playground, checkpoint.playground, corpus),
+            checkpoint.rng_seed,
+            checkpoint.best,
+            checkpoint.last_scores,
+        ),
+        None => {
+            let frequency = match &args.input_table {
+                Some(path) => read_frequency(path)?,
+                None => FrequencyTable::default(),
+            };
+            let mut rng =
+                StdRng::seed_from_u64(config.optimize.seed.expect("seed should be decided"));
+            let playground = Playground::new(&config.playground, &mut rng, frequency, corpus);
+            (playground, rng.gen(), None, Vec::new())
+        }
+    };
 
     let mut bench = Bench::new();
-    let mut playground = Playground::new(50, &mut rng, frequency);
-    let mut best_score = u64::MAX;
-    let mut best_keymap: Option<Keymap> = None;
-    let mut last_scores: Vec<u64> = Vec::new();
-    let conjunctions = read_4gram(Path::new(&path))?;
-    let two_key_timing = TwoKeyTiming::load(Path::new("typing-time.html"))?;
-    let scores = Arc::new(ConnectionScore::new(&two_key_timing));
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
-    let mut no_update_long_time = false;
 
     ctrlc::set_handler(move || {
         r.store(false, Ordering::SeqCst);
     })
     .expect("error setting handler");
 
-    while running.load(Ordering::SeqCst) {
-        let ret = playground.advance(&mut rng, &conjunctions, scores.clone(), no_update_long_time);
+    let save_checkpoint = |playground: &Playground,
+                           rng_seed: u64,
+                           best: &Option<(u64, Keymap)>,
+                           last_scores: &[u64]| {
+        Checkpoint {
+            layout: layout::current().clone(),
+            chars: char_def::current().clone(),
+            config: config.clone(),
+            scoring: scoring.clone(),
+            playground: playground.snapshot(),
+            best: best.clone(),
+            last_scores: last_scores.to_vec(),
+            rng_seed,
+        }
+        .save(&args.checkpoint)
+    };
 
-        if best_score > ret.0 {
+    while running.load(Ordering::SeqCst)
+        && config
+            .optimize
+            .generations
+            .is_none_or(|v| playground.generation() <= v)
+    {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let no_update_long_time = playground
+            .generation()
+            .is_multiple_of(config.optimize.neighbor_search_interval);
+        let (score, keymap) = playground.advance(&mut rng, no_update_long_time);
+        rng_seed = rng.gen();
+
+        if best
+            .as_ref()
+            .is_none_or(|(best_score, _)| *best_score > score)
+        {
             log::info!(
-                "Got new best at {}! score: {}, current best: {} for evaluation:\n{:?}",
+                "Got new best at {}! score: {}, current best: {} for evaluation:\n{}",
                 playground.generation(),
-                ret.0,
-                ret.1,
-                ret.1.key_combinations()
+                score,
+                keymap,
+                export::chutoro::export(&keymap)
             );
 
-            best_score = ret.0;
-            best_keymap = Some(ret.1.clone());
+            best = Some((score, keymap));
         }
 
-        if playground.generation() % 100 == 0 {
-            // log::info!(
-            //     "Long time no best at generation {}. last score: {}, last best score: {}, {} for evaluation:\n{:?}",
-            //     playground.generation(),
-            //     ret.0,
-            //     best_score,
-            //     best_keymap.clone().unwrap(),
-            //     best_keymap.clone().unwrap().key_combinations()
-            // );
-            no_update_long_time = true;
-        } else {
-            no_update_long_time = false;
+        push_score(&mut last_scores, score, config.optimize.score_window);
+        bench.update(playground.generation(), &last_scores);
+
+        if playground
+            .generation()
+            .is_multiple_of(config.optimize.checkpoint_interval)
+        {
+            save_checkpoint(&playground, rng_seed, &best, &last_scores)?;
         }
+    }
 
-        is_mutation_request(&mut last_scores, ret.0);
-        bench.update(playground.generation(), &last_scores);
+    save_checkpoint(&playground, rng_seed, &best, &last_scores)?;
+
+    if let Some((best_score, best_keymap)) = &best {
+        println!(
+            "Score: {}, Best keymap: {} for evaluation:\n{}",
+            best_score,
+            best_keymap,
+            export::chutoro::export(best_keymap)
+        );
+
+        save_keymap(&args.output_keymap, best_keymap)?;
+        config.save(&args.output_keymap.with_extension("config.toml"))?;
     }
 
-    println!(
-        "Score: {}, Best keymap: {} for evaluation:\n{:?}",
-        best_score,
-        best_keymap.clone().unwrap(),
-        best_keymap.unwrap().key_combinations()
-    );
+    save_frequency(&args.output_table, &playground.frequency_table())?;
+    config.save(&args.output_table.with_extension("config.toml"))?;
+
+    Ok(())
+}
 
-    save_frequency(&playground.frequency_table());
+/// 設定を、コマンドラインで指定された値で上書きする
+fn override_config(mut config: Config, args: &OptimizeArgs) -> anyhow::Result<Config> {
+    if let Some(v) = args.population_size {
+        config.playground.keymap_size = v;
+    }
+    if let Some(v) = args.tournament_size {
+        config.playground.tournament_size = v;
+    }
+    if let Some(v) = args.workers {
+        config.playground.workers = v;
+    }
+    if let Some(v) = args.seed {
+        config.optimize.seed = Some(v);
+    }
+    if let Some(v) = args.generations {
+        config.optimize.generations = Some(v);
+    }
+    // 保存した設定から同じ結果を再現できるよう、seedは必ず決定しておく
+    config.optimize.seed.get_or_insert_with(random);
+    config.validate()?;
+
+    log::info!("effective config: {:?}", config);
+    Ok(config)
+}
+
+/// keymapを評価する
+fn evaluate(args: &EvaluateArgs) -> anyhow::Result<()> {
+    let keymap = load_keymap(&args.keymap)?;
+    let conjunctions = read_ngrams(&args.data)?;
+    let scores = connection_score(&args.data)?;
+
+    let score = score::evaluate(&conjunctions, &scores, &keymap);
+    println!("Score: {}", score);
+
+    if args.explain {
+        let explanation = score::explain(&conjunctions, &scores, &keymap, args.worst);
+        let total = explanation.breakdown.total().max(1) as f64;
+
+        println!();
+        for (name, value) in explanation.breakdown.components() {
+            println!(
+                "{:<16}{:>14}\t{:>6.2}%",
+                name,
+                value,
+                value as f64 / total * 100.0
+            );
+        }
+
+        println!();
+        println!("Worst conjunctions:");
+        for worst in explanation.worst {
+            let components = worst
+                .breakdown
+                .components()
+                .iter()
+                .filter(|(_, v)| *v > 0)
+                .map(|(name, v)| format!("{}={}", name, v))
+                .collect::<Vec<_>>();
+            println!("{}\t{}\t{}", worst.text, worst.score, components.join(", "));
+        }
+    }
 
     Ok(())
 }
 
-/// 突然変異を実行するかどうかを判定する。
-///
-/// 突然変異は、score更新前後で平均値が変わらない場合にrequestする
-fn is_mutation_request(scores: &mut Vec<u64>, score: u64) -> bool {
-    let previous_ave = if !scores.is_empty() {
-        scores.iter().sum::<u64>() / scores.len() as u64
-    } else {
-        0
+/// keymapを他の形式で出力する
+fn export(args: &ExportArgs) -> anyhow::Result<()> {
+    let keymap = load_keymap(&args.keymap)?;
+    let text = match args.format {
+        ExportFormat::Text => format!("{}", keymap),
+        ExportFormat::Mozc => export::mozc::export(&keymap),
+        ExportFormat::Keyd => export::chord::keyd(&keymap, args.chord_timeout)?,
+        ExportFormat::Kanata => export::chord::kanata(&keymap, args.chord_timeout)?,
+        ExportFormat::Quail => export::emacs::quail(&keymap),
+        ExportFormat::Skk => export::emacs::skk(&keymap),
+        ExportFormat::Chutoro => export::chutoro::export(&keymap),
+        ExportFormat::Svg | ExportFormat::Html => {
+            let loads = match &args.heatmap {
+                Some(path) => {
+                    let file = File::open(path)
+                        .with_context(|| format!("can not read corpus {}", path.display()))?;
+                    let (conjunctions, _) = ngram::read_conjunctions(file, true)
+                        .with_context(|| format!("invalid corpus {}", path.display()))?;
+                    Some(export::diagram::key_loads(&keymap, &conjunctions))
+                }
+                None => None,
+            };
+
+            match args.format {
+                ExportFormat::Svg => export::diagram::svg(&keymap, loads.as_ref()),
+                _ => export::diagram::html(&keymap, loads.as_ref()),
+            }
+        }
     };
 
-    scores.insert(0, score);
-    scores.truncate(1000);
+    match &args.output {
+        Some(path) => fs::write(path, text)?,
+        None => print!("{}", text),
+    }
+
+    Ok(())
+}
+
+/// keymap同士の評価を比較する
+///
+/// 結果はscoreが良い順に表示し、最も良いkeymapとの差分も表示する
+fn compare(args: &CompareArgs) -> anyhow::Result<()> {
+    let keymaps = args
+        .keymaps
+        .iter()
+        .map(|path| load_keymap(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let conjunctions = read_ngrams(&args.data)?;
+    let scores = connection_score(&args.data)?;
+
+    let mut results = args
+        .keymaps
+        .iter()
+        .zip(keymaps.iter())
+        .map(|(path, keymap)| {
+            (
+                path,
+                u64::from(score::evaluate(&conjunctions, &scores, keymap)),
+            )
+        })
+        .collect::<Vec<_>>();
+    results.sort_by_key(|(_, score)| *score);
+
+    let best = results[0].1;
+    for (path, score) in results {
+        println!(
+            "{}\t{}\t+{:.2}%",
+            score,
+            path.display(),
+            (score - best) as f64 / best.max(1) as f64 * 100.0
+        );
+    }
 
-    let current_ave = scores.iter().sum::<u64>() / scores.len() as u64;
+    Ok(())
+}
 
-    previous_ave == current_ave
+/// typing-time.html形式の打鍵時間をCSV/TSV形式に変換する
+///
+/// 変換しなかったセルの数は、黙って捨てずに標準エラーに表示する
+fn convert_timing(args: &ConvertTimingArgs) -> anyhow::Result<()> {
+    let conversion = timing::convert_html(&args.input, args.output.as_deref(), args.keep_capped)
+        .with_context(|| format!("failed to convert {}", args.input.display()))?;
+
+    eprintln!(
+        "converted {} timings, skipped {} unmeasured (0ms) cells, {} {} capped (1000ms) cells",
+        conversion.records.len(),
+        conversion.unmeasured,
+        if args.keep_capped { "kept" } else { "skipped" },
+        conversion.capped
+    );
+
+    Ok(())
+}
+
+/// 複数の打鍵時間を集約して出力する
+///
+/// 計測した人による差が大きい組み合わせは、標準エラーに表示する
+fn aggregate_timing(args: &AggregateTimingArgs) -> anyhow::Result<()> {
+    let aggregated = aggregate_timings(&args.timing)?;
+    timing::save_records(args.output.as_deref(), &aggregated.records)?;
+
+    eprintln!(
+        "aggregated {} timings from {} files",
+        aggregated.records.len(),
+        args.timing.timings.len()
+    );
+    for disagreement in aggregated
+        .disagreements
+        .iter()
+        .take_while(|v| v.spread() >= args.disagreement)
+    {
+        let values = disagreement
+            .values
+            .iter()
+            .map(|v| v.map_or("-".to_string(), |v| v.to_string()))
+            .collect::<Vec<_>>();
+        eprintln!(
+            "{}{}\t{}\t{:.0}%\t{}",
+            disagreement.from,
+            disagreement.to,
+            disagreement.aggregated,
+            disagreement.spread() * 100.0,
+            values.join(" ")
+        );
+    }
+
+    Ok(())
+}
+
+/// 端末上で打鍵時間を計測する
+#[cfg(unix)]
+fn measure_timing(args: &MeasureTimingArgs) -> anyhow::Result<()> {
+    measure::measure(&args.output, args.keys.as_deref(), args.trials as usize)
+}
+
+/// テキストのコーパスからn-gramの出現回数を数えて出力する
+fn count_ngrams(args: &CountNgramsArgs) -> anyhow::Result<()> {
+    let mut counter = ngram::NgramCounter::new(&args.lengths, args.max_entries)?;
+    let files = ngram::collect_files(&args.inputs)?;
+
+    for path in files.iter() {
+        let file =
+            File::open(path).with_context(|| format!("can not read corpus {}", path.display()))?;
+        counter
+            .feed_reader(file)
+            .with_context(|| format!("failed to read {} as UTF-8 text", path.display()))?;
+        log::info!("counted {}", path.display());
+    }
+
+    let (chars, max_error) = (counter.chars(), counter.max_error());
+    let counts = counter.finish(args.min_count);
+    ngram::save_counts(args.output.as_deref(), &counts)?;
+
+    eprintln!(
+        "counted {} n-grams in {} chars from {} files",
+        counts.len(),
+        chars,
+        files.len()
+    );
+    if max_error > 0 {
+        eprintln!(
+            "n-grams exceeded --max-entries, so counts may be up to {} less than actual",
+            max_error
+        );
+    }
+
+    Ok(())
+}
+
+/// 複数のn-gramの出現回数を合算して出力する
+fn merge_ngrams(args: &MergeNgramsArgs) -> anyhow::Result<()> {
+    if !args.weights.is_empty() && args.weights.len() != args.inputs.len() {
+        anyhow::bail!(
+            "{} weights are given for {} inputs",
+            args.weights.len(),
+            args.inputs.len()
+        );
+    }
+    if args.weights.iter().any(|v| !v.is_finite() || *v < 0.0) {
+        anyhow::bail!("weights should not be negative");
+    }
+
+    let sources = args
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(idx, path)| {
+            let file = File::open(path)
+                .with_context(|| format!("can not read n-grams {}", path.display()))?;
+            let counts = ngram::read_counts(file)
+                .with_context(|| format!("invalid n-grams {}", path.display()))?;
+            Ok((counts, args.weights.get(idx).copied().unwrap_or(1.0)))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let merged = ngram::merge(&sources);
+    ngram::save_counts(args.output.as_deref(), &merged)?;
+    eprintln!(
+        "merged {} n-grams from {} files",
+        merged.len(),
+        sources.len()
+    );
+
+    Ok(())
+}
+
+/// 頻度表について、キーごとに選択されやすい文字を表示する
+fn inspect_table(args: &InspectTableArgs) -> anyhow::Result<()> {
+    let table = read_frequency(&args.table)?;
+
+    for (point, frequency) in linear::linear_layout()
+        .iter()
+        .zip(table.frequencies().iter())
+    {
+        println!("{}:", linear::get_char_of_point(point));
+
+        for (layer, probabilities) in frequency.probabilities() {
+            let chars = probabilities
+                .iter()
+                .take(args.top)
+                .map(|(c, prob)| format!("{}({:.2}%)", c.normal(), prob * 100.0))
+                .collect::<Vec<_>>();
+
+            println!("  {:<6} {}", layer, chars.join(" "));
+        }
+    }
+
+    Ok(())
+}
+
+/// 直近 `window` 個のscoreに、最新の `score` を先頭として追加する。
+///
+/// 進捗の表示で、平均のscoreと直近のscoreに利用する
+fn push_score(scores: &mut Vec<u64>, score: u64, window: usize) {
+    scores.insert(0, score);
+    scores.truncate(window);
 }
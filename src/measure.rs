@@ -0,0 +1,279 @@
+use std::{
+    collections::HashSet,
+    io::{self, IsTerminal, Read, Write},
+    path::Path,
+    time::Instant,
+};
+
+use anyhow::Context;
+use nix::sys::termios::{self, LocalFlags, SetArg, SpecialCharacterIndices, Termios};
+
+use crate::{
+    layout::linear::{get_char_of_point, linear_layout},
+    timing::{self, TimingRecord},
+};
+
+/// 計測を中断するキー(Ctrl-C)
+const INTERRUPT: char = '\u{3}';
+
+/// 計測中のキーの組み合わせを飛ばすキー(Esc)
+const SKIP: char = '\u{1b}';
+
+/// 標準入力を、1文字ずつエコーせずに読み込む状態にする。
+///
+/// dropした時点で元の状態に戻す
+struct RawInput {
+    original: Termios,
+}
+
+impl RawInput {
+    fn enable() -> anyhow::Result<Self> {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            anyhow::bail!("timing measurement needs a terminal as stdin");
+        }
+
+        let original = termios::tcgetattr(&stdin)?;
+        let mut raw = original.clone();
+        // Ctrl-Cも1文字として読み込み、中断時に計測結果を保存できるようにする
+        raw.local_flags
+            .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG);
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        termios::tcsetattr(&stdin, SetArg::TCSANOW, &raw)?;
+
+        Ok(RawInput { original })
+    }
+
+    /// 1文字読み込み、読み込みを開始した時刻と共に返す
+    fn read_char(&self) -> anyhow::Result<(char, Instant)> {
+        let mut stdin = io::stdin().lock();
+        let mut buf = [0u8; 4];
+        stdin.read_exact(&mut buf[..1])?;
+        let time = Instant::now();
+
+        // UTF-8の先頭byteから、残りのbyte数を決める
+        let len = match buf[0] {
+            0xf0.. => 4,
+            0xe0.. => 3,
+            0xc0.. => 2,
+            _ => 1,
+        };
+        stdin.read_exact(&mut buf[1..len])?;
+        let c = std::str::from_utf8(&buf[..len])
+            .ok()
+            .and_then(|v| v.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+
+        Ok((c, time))
+    }
+}
+
+impl Drop for RawInput {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(io::stdin(), SetArg::TCSANOW, &self.original);
+    }
+}
+
+/// 1つのキーの組み合わせについて、打鍵間隔を記録する
+#[derive(Debug)]
+struct Trial {
+    from: char,
+    to: char,
+    /// 1つ目のキーを押下した時刻。2つ目のキーを待っていない場合はNone
+    pressed: Option<Instant>,
+    intervals: Vec<u32>,
+}
+
+impl Trial {
+    fn new(from: char, to: char) -> Self {
+        Trial {
+            from,
+            to,
+            pressed: None,
+            intervals: Vec::new(),
+        }
+    }
+
+    /// 打鍵を記録する。
+    ///
+    /// 1つ目のキーの次に2つ目のキーを押下した場合のみ、その間隔を記録する。
+    /// 間違えたキーを押下した場合は、1つ目のキーからやりなおす
+    fn press(&mut self, c: char, time: Instant) {
+        match self.pressed {
+            Some(pressed) if c == self.to => {
+                let interval = time.duration_since(pressed).as_millis();
+                self.intervals.push(interval.min(u32::MAX as u128) as u32);
+                self.pressed = None;
+            }
+            _ if c == self.from => self.pressed = Some(time),
+            _ => self.pressed = None,
+        }
+    }
+}
+
+/// 打鍵間隔から外れ値を除き、平均した打鍵時間と利用した回数を返す。
+///
+/// 外れ値は、四分位範囲の1.5倍より外側にある値とする
+fn summarize(intervals: &[u32]) -> Option<(u32, u32)> {
+    if intervals.is_empty() {
+        return None;
+    }
+
+    let mut sorted = intervals.to_vec();
+    sorted.sort();
+    let quartile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize] as f64;
+    let (q1, q3) = (quartile(0.25), quartile(0.75));
+    let range = (q1 - (q3 - q1) * 1.5)..=(q3 + (q3 - q1) * 1.5);
+
+    let kept = sorted
+        .iter()
+        .filter(|v| range.contains(&(**v as f64)))
+        .collect::<Vec<_>>();
+    let mean = kept.iter().map(|v| **v as f64).sum::<f64>() / kept.len() as f64;
+
+    Some((mean.round() as u32, kept.len() as u32))
+}
+
+/// 計測するキーの組み合わせを、レイアウトの順序で返す
+fn pairs_to_measure(keys: Option<&str>) -> anyhow::Result<Vec<(char, char)>> {
+    let labels = linear_layout()
+        .iter()
+        .map(get_char_of_point)
+        .collect::<Vec<_>>();
+    let labels = match keys {
+        Some(keys) => {
+            if let Some(c) = keys.chars().find(|c| !labels.contains(c)) {
+                anyhow::bail!("key '{}' is not assignable in the layout", c);
+            }
+            labels
+                .into_iter()
+                .filter(|c| keys.contains(*c))
+                .collect::<Vec<_>>()
+        }
+        None => labels,
+    };
+
+    Ok(labels
+        .iter()
+        .flat_map(|from| labels.iter().map(|to| (*from, *to)))
+        .collect())
+}
+
+/// 端末上で2キーの打鍵時間を計測し、`output` に保存する。
+///
+/// `output` が既に存在する場合は、記録済みの組み合わせを飛ばして続きから計測する。
+/// 組み合わせを1つ計測するごとに保存するため、途中で中断しても計測結果は失われない
+pub fn measure(output: &Path, keys: Option<&str>, trials: usize) -> anyhow::Result<()> {
+    let mut records = if output.exists() {
+        timing::read_source(output)
+            .with_context(|| format!("failed to load timing {}", output.display()))?
+    } else {
+        Vec::new()
+    };
+    let measured = records
+        .iter()
+        .map(|v| (v.from, v.to))
+        .collect::<HashSet<_>>();
+    let pairs = pairs_to_measure(keys)?
+        .into_iter()
+        .filter(|v| !measured.contains(v))
+        .collect::<Vec<_>>();
+
+    let input = RawInput::enable()?;
+    let mut stdout = io::stdout().lock();
+    writeln!(
+        stdout,
+        "Type each pair {} times. Esc skips the pair, Ctrl-C saves and quits.",
+        trials
+    )?;
+
+    'pairs: for (idx, (from, to)) in pairs.iter().enumerate() {
+        let mut trial = Trial::new(*from, *to);
+        write!(stdout, "[{}/{}] {}{}: ", idx + 1, pairs.len(), from, to)?;
+        stdout.flush()?;
+
+        while trial.intervals.len() < trials {
+            let (c, time) = input.read_char()?;
+            match c {
+                INTERRUPT => {
+                    writeln!(stdout, "interrupted")?;
+                    break 'pairs;
+                }
+                SKIP => {
+                    writeln!(stdout, "skipped")?;
+                    continue 'pairs;
+                }
+                _ => {
+                    let count = trial.intervals.len();
+                    trial.press(c, time);
+                    if trial.intervals.len() > count {
+                        write!(stdout, ".")?;
+                        stdout.flush()?;
+                    }
+                }
+            }
+        }
+
+        if let Some((millis, samples)) = summarize(&trial.intervals) {
+            writeln!(stdout, " {}ms", millis)?;
+            records.push(TimingRecord {
+                from: *from,
+                to: *to,
+                millis,
+                samples: Some(samples),
+            });
+            timing::save_records(Some(output), &records)?;
+        }
+    }
+
+    drop(input);
+    writeln!(
+        stdout,
+        "{} timings are saved to {}",
+        records.len(),
+        output.display()
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn trial_records_only_intervals_of_the_pair() {
+        // arrange
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut trial = Trial::new('a', 's');
+
+        // act
+        trial.press('a', at(0));
+        trial.press('s', at(120));
+        trial.press('a', at(300));
+        trial.press('d', at(400));
+        trial.press('s', at(450));
+        trial.press('a', at(600));
+        trial.press('s', at(710));
+
+        // assert
+        assert_eq!(trial.intervals, vec![120, 110]);
+    }
+
+    #[test]
+    fn summarize_discards_outliers() {
+        // arrange
+        let intervals = [120, 110, 900, 130, 115, 125, 20];
+
+        // act
+        let summary = summarize(&intervals);
+
+        // assert
+        assert_eq!(summary, Some((120, 5)));
+        assert_eq!(summarize(&[]), None);
+    }
+}
@@ -0,0 +1,589 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{char_def, score::Conjunction};
+
+/// n-gramの出現回数ファイルのheader
+const HEADER: [&str; 2] = ["text", "count"];
+
+/// n-gramと、その出現回数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NgramCount {
+    pub text: String,
+    pub count: u64,
+}
+
+/// カタカナをひらがなに変換する。それ以外の文字はそのまま返す
+fn normalize(c: char) -> char {
+    match c {
+        // ァ..=ヶ は、ぁ..=ゖ と同じ順序で並んでいる
+        'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// `feed_reader` で一度に読み込むバイト数
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// テキストからn-gramの出現回数を数える。
+///
+/// 評価で利用しない文字はn-gramの区切りとして扱い、それを跨ぐn-gramは数えない。
+/// 記録するn-gramの種類が `capacity` を超えた場合は、Lossy Countingの要領で出現回数が少ないものから捨てる。
+/// このため、出現回数は最大で[NgramCounter::max_error]だけ少なく数えられている可能性がある
+#[derive(Debug)]
+pub struct NgramCounter {
+    lengths: Vec<usize>,
+    capacity: usize,
+    inventory: HashSet<char>,
+    /// n-gramごとの、記録してからの出現回数と、記録する前に捨てられていた可能性がある出現回数の上限
+    counts: HashMap<String, (u64, u64)>,
+    /// これまでに捨てたn-gramの出現回数の閾値。新しく記録するn-gramの誤差の上限になる
+    threshold: u64,
+    /// 評価で利用する文字だけが連続している、直近の文字。最長のn-gramの長さまで保持する
+    run: Vec<char>,
+    /// 読み込んだ文字数
+    chars: u64,
+}
+
+impl NgramCounter {
+    /// # Arguments
+    /// * `lengths` - 数えるn-gramの長さ
+    /// * `capacity` - 記録するn-gramの種類の上限
+    pub fn new(lengths: &[usize], capacity: usize) -> anyhow::Result<Self> {
+        if lengths.is_empty() || lengths.contains(&0) {
+            anyhow::bail!("n-gram lengths should be positive");
+        }
+        if capacity < 2 {
+            anyhow::bail!("capacity should be at least 2");
+        }
+
+        Ok(NgramCounter {
+            lengths: lengths.to_vec(),
+            capacity,
+            inventory: char_def::all_chars().into_iter().map(|(_, c)| c).collect(),
+            counts: HashMap::new(),
+            threshold: 0,
+            run: Vec::new(),
+            chars: 0,
+        })
+    }
+
+    /// テキストを数える。
+    ///
+    /// 直前に数えたテキストの末尾からn-gramが続くものとして扱う。続けない場合は[NgramCounter::end_run]を呼ぶ
+    pub fn feed(&mut self, text: &str) {
+        for c in text.chars().map(normalize) {
+            self.chars += 1;
+            if self.inventory.contains(&c) {
+                self.push(c);
+            } else {
+                self.end_run();
+            }
+        }
+    }
+
+    /// 評価で利用しない文字があったものとして、n-gramを区切る
+    pub fn end_run(&mut self) {
+        self.run.clear();
+    }
+
+    /// `c` を追加し、 `c` で終わるn-gramを数える
+    fn push(&mut self, c: char) {
+        let longest = self.lengths.iter().max().copied().unwrap_or(1);
+        if self.run.len() == longest {
+            self.run.remove(0);
+        }
+        self.run.push(c);
+
+        for idx in 0..self.lengths.len() {
+            let length = self.lengths[idx];
+            if length > self.run.len() {
+                continue;
+            }
+
+            let ngram = self.run[self.run.len() - length..].iter().collect();
+            self.counts.entry(ngram).or_insert((0, self.threshold)).0 += 1;
+
+            if self.counts.len() > self.capacity {
+                self.prune();
+            }
+        }
+    }
+
+    /// 記録しているn-gramが `capacity` の半分以下になるまで、出現回数と誤差の上限の和が少ないものを捨てる
+    fn prune(&mut self) {
+        let keep = self.capacity / 2;
+        // 残す数を超えないthresholdのうち最小のものは、(keep + 1)番目に大きい値になる
+        let mut sums = self
+            .counts
+            .values()
+            .map(|(count, error)| *count + *error)
+            .collect::<Vec<_>>();
+        let threshold = if sums.len() > keep {
+            *sums.select_nth_unstable_by(keep, |a, b| b.cmp(a)).1
+        } else {
+            0
+        }
+        .max(self.threshold + 1);
+
+        self.counts
+            .retain(|_, (count, error)| *count + *error > threshold);
+
+        log::debug!("pruned n-grams appeared {} times or less", threshold);
+        self.threshold = threshold;
+    }
+
+    /// `reader` のテキストを全て数える。
+    ///
+    /// 一定のバイト数ずつ読み込むため、改行を含まないテキストでも使用するメモリは増えない
+    pub fn feed_reader<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        // 前回読み込んだうち、UTF-8の文字として途中で終わっていたバイト数
+        let mut pending = 0;
+
+        loop {
+            let read = reader.read(&mut buf[pending..])?;
+            if read == 0 {
+                break;
+            }
+            let end = pending + read;
+
+            let valid = match std::str::from_utf8(&buf[..end]) {
+                Ok(text) => text.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            };
+            let text = std::str::from_utf8(&buf[..valid]).expect("should be valid UTF-8");
+            self.feed(text);
+
+            buf.copy_within(valid..end, 0);
+            pending = end - valid;
+        }
+        self.end_run();
+
+        if pending > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not end with a complete UTF-8 char",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// 記録しているn-gramの出現回数が、少なく数えられている可能性がある最大の回数
+    pub fn max_error(&self) -> u64 {
+        self.counts
+            .values()
+            .map(|(_, error)| *error)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 読み込んだ文字数
+    pub fn chars(&self) -> u64 {
+        self.chars
+    }
+
+    /// 出現回数が `min_count` 以上のn-gramを、出現回数が多い順に返す
+    pub fn finish(self, min_count: u64) -> Vec<NgramCount> {
+        let counts = self
+            .counts
+            .into_iter()
+            .filter(|(_, (count, _))| *count >= min_count)
+            .map(|(text, (count, _))| NgramCount { text, count })
+            .collect();
+
+        sorted(counts)
+    }
+}
+
+/// 出現回数が多い順、同じ場合はテキスト順に並べる
+fn sorted(mut counts: Vec<NgramCount>) -> Vec<NgramCount> {
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.text.cmp(&b.text)));
+    counts
+}
+
+/// `paths` に含まれるファイルを返す。ディレクトリの場合は、その中のファイルを再帰的に名前順で返す
+pub fn collect_files(paths: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+
+        let mut entries = fs::read_dir(path)
+            .with_context(|| format!("can not read directory {}", path.display()))?
+            .map(|v| v.map(|v| v.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        files.extend(collect_files(&entries)?);
+    }
+
+    Ok(files)
+}
+
+/// タブ区切りのn-gramの出現回数を読み込む
+pub fn read_counts<R: Read>(reader: R) -> anyhow::Result<Vec<NgramCount>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_reader(reader);
+
+    let mut counts = Vec::new();
+    for result in rdr.records() {
+        let record = result?;
+        let line = record.position().map_or(0, |v| v.line());
+        let (Some(text), Some(count)) = (record.get(0), record.get(1)) else {
+            anyhow::bail!("line {}: expected text and count", line);
+        };
+        let count = count
+            .parse::<u64>()
+            .with_context(|| format!("line {}: invalid count '{}'", line, count))?;
+
+        counts.push(NgramCount {
+            text: text.to_string(),
+            count,
+        });
+    }
+
+    Ok(counts)
+}
+
+/// n-gramの出現回数をタブ区切りで書き出す。
+///
+/// 評価時には出現回数をu32で扱うため、それを超える出現回数は上限値に丸める
+pub fn write_counts<W: Write>(writer: W, counts: &[NgramCount]) -> anyhow::Result<()> {
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_writer(writer);
+
+    wtr.write_record(HEADER)?;
+    for count in counts {
+        wtr.write_record([
+            count.text.clone(),
+            count.count.min(u32::MAX as u64).to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// n-gramの出現回数を `output` に書き出す。Noneの場合は標準出力に出力する
+pub fn save_counts(output: Option<&Path>, counts: &[NgramCount]) -> anyhow::Result<()> {
+    match output {
+        Some(path) => write_counts(
+            File::create(path).with_context(|| format!("can not create {}", path.display()))?,
+            counts,
+        ),
+        None => write_counts(io::stdout().lock(), counts),
+    }
+}
+
+/// 複数の出現回数を、(出現回数, 重み)の組ごとに重みを掛けて合算する。
+///
+/// 合算した出現回数は四捨五入し、0になったn-gramは除く
+pub fn merge(sources: &[(Vec<NgramCount>, f64)]) -> Vec<NgramCount> {
+    let mut merged: HashMap<&str, f64> = HashMap::new();
+    for (counts, weight) in sources {
+        for count in counts {
+            *merged.entry(&count.text).or_default() += count.count as f64 * weight;
+        }
+    }
+
+    let counts = merged
+        .into_iter()
+        .map(|(text, count)| NgramCount {
+            text: text.to_string(),
+            count: count.round() as u64,
+        })
+        .filter(|v| v.count > 0)
+        .collect();
+
+    sorted(counts)
+}
+
+/// 連接を読み込む際に、評価で利用しない文字を含んでいたn-gramの統計
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorpusStats {
+    /// 読み込んだn-gramの数
+    pub ngrams: usize,
+    /// 読み込んだn-gramの出現回数の合計
+    pub appearances: u64,
+    /// 評価で利用しない文字を含むため、除外したn-gramの数
+    pub dropped_ngrams: usize,
+    /// 除外したn-gramの出現回数の合計
+    pub dropped_appearances: u64,
+    /// 評価で利用しない文字で分割したn-gramの数
+    pub split_ngrams: usize,
+    /// 評価で利用しない文字ごとの、その文字を含んでいたn-gramの出現回数の合計
+    pub unknown_chars: HashMap<char, u64>,
+}
+
+impl CorpusStats {
+    /// 出現回数の合計に対する、除外した出現回数の比率
+    pub fn dropped_ratio(&self) -> f64 {
+        self.dropped_appearances as f64 / self.appearances.max(1) as f64
+    }
+
+    /// 評価で利用しない文字を、含んでいたn-gramの出現回数が多い順に返す
+    pub fn unknown_chars(&self) -> Vec<(char, u64)> {
+        let mut chars = self
+            .unknown_chars
+            .iter()
+            .map(|(c, count)| (*c, *count))
+            .collect::<Vec<_>>();
+        chars.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        chars
+    }
+}
+
+/// タブ区切りのn-gramの出現回数から、評価に利用する連接を読み込む。
+///
+/// 評価で利用しない文字を含むn-gramは、`split_unknown` であればその文字で分割して残りを利用し、
+/// そうでなければn-gram全体を除外する。同じテキストの連接は出現回数を合算して1つにし、テキストの順に並べる
+pub fn read_conjunctions<R: Read>(
+    reader: R,
+    split_unknown: bool,
+) -> anyhow::Result<(Vec<Conjunction>, CorpusStats)> {
+    let all_chars = char_def::all_chars();
+    let char_position_map: HashMap<char, usize> = all_chars
+        .iter()
+        .enumerate()
+        .map(|(idx, v)| (v.1, idx))
+        .collect();
+    let mut stats = CorpusStats::default();
+    let mut conjunctions: Vec<Conjunction> = Vec::new();
+
+    for ngram in read_counts(reader)? {
+        stats.ngrams += 1;
+        stats.appearances += ngram.count;
+
+        let unknown = ngram
+            .text
+            .chars()
+            .filter(|c| !char_position_map.contains_key(c))
+            .collect::<HashSet<_>>();
+        for c in unknown.iter() {
+            *stats.unknown_chars.entry(*c).or_default() += ngram.count;
+        }
+
+        let texts = if unknown.is_empty() {
+            vec![ngram
+                .text
+                .chars()
+                .map(|c| char_position_map[&c])
+                .collect::<Vec<_>>()]
+        } else if split_unknown {
+            stats.split_ngrams += 1;
+            ngram
+                .text
+                .split(|c| unknown.contains(&c))
+                .filter(|v| !v.is_empty())
+                .map(|v| v.chars().map(|c| char_position_map[&c]).collect())
+                .collect()
+        } else {
+            stats.dropped_ngrams += 1;
+            stats.dropped_appearances += ngram.count;
+            continue;
+        };
+
+        let appearances = ngram.count.min(u32::MAX as u64) as u32;
+        for text in texts.into_iter().filter(|v| !v.is_empty()) {
+            conjunctions.push(Conjunction::new(text, appearances, &all_chars));
+        }
+    }
+
+    // 連接のテキストを別に保持するとメモリの使用量が倍になるため、mapではなく整列して重複を合算する
+    conjunctions.sort_unstable_by(|a, b| a.text.cmp(&b.text));
+    conjunctions.dedup_by(|current, kept| {
+        if current.text != kept.text {
+            return false;
+        }
+        kept.appearances = kept.appearances.saturating_add(current.appearances);
+        true
+    });
+    conjunctions.shrink_to_fit();
+
+    Ok((conjunctions, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(text: &str, count: u64) -> NgramCount {
+        NgramCount {
+            text: text.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn count_hiragana_split_by_unknown_chars() {
+        // arrange
+        let mut counter = NgramCounter::new(&[2, 3], 100).unwrap();
+
+        // act
+        counter.feed("カタカナ漢字かな\n");
+        let counts = counter.finish(1);
+
+        // assert
+        assert_eq!(
+            counts,
+            vec![
+                count("かな", 2),
+                count("かた", 1),
+                count("かたか", 1),
+                count("たか", 1),
+                count("たかな", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_frequent_ngrams_within_capacity() {
+        // arrange
+        let mut counter = NgramCounter::new(&[1], 4).unwrap();
+
+        // act
+        counter.feed("あああああいいいいう");
+        counter.feed("えおかきく");
+        let max_error = counter.max_error();
+        let counts = counter.finish(1);
+
+        // assert
+        assert!(counts.len() <= 4);
+        assert_eq!(counts[0], count("あ", 5));
+        assert_eq!(counts[1], count("い", 4));
+        assert_eq!(max_error, 0, "kept n-grams are never pruned");
+    }
+
+    #[test]
+    fn bound_error_of_ngram_pruned_twice() {
+        // arrange
+        let mut counter = NgramCounter::new(&[1], 2).unwrap();
+
+        // act
+        // あ is pruned at the threshold 1, then 2 after reinserted
+        counter.feed("あいう");
+        counter.feed("あかかき");
+        counter.feed("あああ");
+        let max_error = counter.max_error();
+        let counts = counter.finish(1);
+
+        // assert
+        assert_eq!(counts, vec![count("あ", 3), count("か", 2)]);
+        assert_eq!(max_error, 2);
+        assert!(counts[0].count + max_error >= 5, "あ appeared 5 times");
+    }
+
+    #[test]
+    fn prune_to_half_at_once() {
+        // arrange
+        let mut counter = NgramCounter::new(&[1], 4).unwrap();
+
+        // act
+        // お makes 5 n-grams, so those appeared 3 times or less are pruned at once
+        counter.feed("ああああいいいいうううえええおおお");
+        counter.feed("か");
+        let max_error = counter.max_error();
+        let counts = counter.finish(1);
+
+        // assert
+        assert_eq!(
+            counts,
+            vec![
+                count("あ", 4),
+                count("い", 4),
+                count("お", 2),
+                count("か", 1)
+            ]
+        );
+        assert_eq!(max_error, 3);
+    }
+
+    #[test]
+    fn feed_reader_in_chunks() {
+        // arrange
+        let text = "かな".repeat(CHUNK_SIZE);
+        let mut counter = NgramCounter::new(&[2], 100).unwrap();
+
+        // act
+        counter.feed_reader(text.as_bytes()).unwrap();
+        let ret = NgramCounter::new(&[2], 100)
+            .unwrap()
+            .feed_reader(&[0xe3, 0x81][..]);
+        let counts = counter.finish(1);
+
+        // assert
+        assert_eq!(
+            counts,
+            vec![
+                count("かな", CHUNK_SIZE as u64),
+                count("なか", CHUNK_SIZE as u64 - 1)
+            ]
+        );
+        assert!(ret.is_err(), "truncated char should be an error");
+    }
+
+    #[test]
+    fn merge_with_weights() {
+        // arrange
+        let sources = vec![
+            (vec![count("かな", 10), count("あい", 1)], 1.0),
+            (vec![count("かな", 4), count("うえ", 3)], 0.5),
+        ];
+
+        // act
+        let merged = merge(&sources);
+        let mut buf = Vec::new();
+        write_counts(&mut buf, &merged).unwrap();
+
+        // assert
+        assert_eq!(
+            merged,
+            vec![count("かな", 12), count("うえ", 2), count("あい", 1)]
+        );
+        assert_eq!(read_counts(buf.as_slice()).unwrap(), merged);
+    }
+
+    #[test]
+    fn drop_or_split_ngrams_with_unknown_chars() {
+        // arrange
+        let text = "text\tcount\nかなかな\t3\nかな・あ\t2\nアかな\t1\n";
+        let chars = char_def::all_chars();
+        let index = |c: char| chars.iter().position(|(_, v)| *v == c).unwrap();
+
+        // act
+        let (dropped, dropped_stats) = read_conjunctions(text.as_bytes(), false).unwrap();
+        let (split, split_stats) = read_conjunctions(text.as_bytes(), true).unwrap();
+
+        // assert
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped_stats.dropped_ngrams, 2);
+        assert_eq!(dropped_stats.dropped_appearances, 3);
+        assert_eq!(dropped_stats.unknown_chars(), vec![('・', 2), ('ア', 1)]);
+        assert_eq!(
+            split
+                .iter()
+                .map(|v| (v.text.clone(), v.appearances))
+                .collect::<Vec<_>>(),
+            vec![
+                (vec![index('あ')], 2),
+                (vec![index('か'), index('な')], 3),
+                (vec![index('か'), index('な'), index('か'), index('な')], 3),
+            ]
+        );
+        assert_eq!(split_stats.split_ngrams, 2);
+        assert_eq!(split_stats.dropped_appearances, 0);
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    connection_score::ScoreBreakdown,
+    hand_profile::HandProfile,
+    key_seq::Stroke,
+    layout::{Finger, Layout, Point},
+};
+
+/// 組み込みのルール。名前とTOMLの組
+const PRESETS: [(&str, &str); 1] = [("default", include_str!("../rules/default.toml"))];
+
+/// 連接する2キーに対する条件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Predicate {
+    /// 同じ手で押下する
+    SameHand,
+    /// 異なる手で押下する
+    AlternateHand,
+    /// 同じ手の同じ指で押下する
+    SameFinger,
+    /// 同じ指で段を飛ばして押下する
+    SameFingerSkipRow,
+    /// 同じ指で異なる段・異なる列を押下する
+    SameFingerDance,
+    /// 同じ手の異なる指で段を飛ばして押下する
+    SkipRow,
+    /// レイアウトで定義された、押下しやすい組み合わせである
+    Arpeggio,
+    /// 同じ手で、小指側から人差し指側に向かって押下する
+    InwardRoll,
+    /// 同じ手で、人差し指側から小指側に向かって押下する
+    OutwardRoll,
+    /// ルールで指定したlistに含まれる組み合わせである
+    InList,
+    /// いずれも小指で押下する
+    Pinky,
+}
+
+/// ルールファイルにおける1ルール
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDefinition {
+    keys: usize,
+    predicate: Predicate,
+    weight: u32,
+    #[serde(default)]
+    negate: bool,
+    #[serde(default)]
+    chord: bool,
+    list: Option<String>,
+}
+
+/// ルールファイルの内容
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSetDefinition {
+    rules: Vec<RuleDefinition>,
+    #[serde(default)]
+    lists: HashMap<String, Vec<[char; 2]>>,
+}
+
+/// 位置を解決したルール
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    keys: usize,
+    predicate: Predicate,
+    weight: u64,
+    negate: bool,
+    /// シフトキーとの同時押しに対するルールかどうか
+    chord: bool,
+    /// [RuleSet::lists]におけるindex
+    list: Option<usize>,
+}
+
+/// 連接に対するペナルティのルール。
+///
+/// ルールファイルはTOMLで記述する。記述例は `rules/default.toml` を参照。
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    /// 押下しやすい組み合わせ。両方向の組を持つ
+    arpeggios: HashSet<(Point, Point)>,
+    /// ルールファイルで定義したlist。両方向の組を持つ
+    lists: Vec<HashSet<(Point, Point)>>,
+}
+
+/// 人差し指から数えた指の位置
+fn finger_rank(finger: Finger) -> u8 {
+    match finger {
+        Finger::Thumb => 0,
+        Finger::Index => 1,
+        Finger::Middle => 2,
+        Finger::Ring => 3,
+        Finger::Pinky => 4,
+    }
+}
+
+impl RuleSet {
+    /// 組み込みのルール名、またはファイルのパスからルールを読み込む
+    pub fn load(name_or_path: &str, layout: &Layout) -> anyhow::Result<RuleSet> {
+        let text = match PRESETS.iter().find(|(name, _)| *name == name_or_path) {
+            Some((_, text)) => text.to_string(),
+            None => fs::read_to_string(Path::new(name_or_path)).with_context(|| {
+                format!(
+                    "rules {} is neither a preset ({}) nor a readable file",
+                    name_or_path,
+                    PRESETS.map(|(name, _)| name).join(", ")
+                )
+            })?,
+        };
+
+        RuleSet::parse(&text, layout).with_context(|| format!("invalid rules {}", name_or_path))
+    }
+
+    /// 組み込みの既定のルールを返す
+    pub fn builtin(layout: &Layout) -> RuleSet {
+        RuleSet::parse(PRESETS[0].1, layout).expect("builtin rules should be valid")
+    }
+
+    /// TOMLの文字列からルールを読み込む
+    pub fn parse(text: &str, layout: &Layout) -> anyhow::Result<RuleSet> {
+        let definition: RuleSetDefinition = toml::from_str(text)?;
+        let point_of = |label: char| {
+            layout
+                .key_of_label(label)
+                .map(|v| v.point())
+                .ok_or_else(|| anyhow::anyhow!("key '{}': not in the layout", label))
+        };
+
+        let mut names = definition.lists.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        let lists = names
+            .iter()
+            .map(|name| {
+                let mut pairs = HashSet::new();
+                for [first, second] in definition.lists[name].iter() {
+                    let (first, second) = (point_of(*first)?, point_of(*second)?);
+                    pairs.insert((first, second));
+                    pairs.insert((second, first));
+                }
+                Ok(pairs)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("invalid lists")?;
+
+        let rules = definition
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(idx, rule)| {
+                if !(2..=3).contains(&rule.keys) {
+                    anyhow::bail!("rules[{}]: keys should be 2 or 3", idx);
+                }
+                let list = match (rule.predicate, &rule.list) {
+                    (Predicate::InList, Some(name)) => {
+                        Some(names.iter().position(|v| v == name).ok_or_else(|| {
+                            anyhow::anyhow!("rules[{}]: list '{}' is not defined", idx, name)
+                        })?)
+                    }
+                    (Predicate::InList, None) => {
+                        anyhow::bail!("rules[{}]: in-list needs a list", idx)
+                    }
+                    (_, Some(_)) => anyhow::bail!("rules[{}]: list is only for in-list", idx),
+                    (_, None) => None,
+                };
+
+                Ok(Rule {
+                    keys: rule.keys,
+                    predicate: rule.predicate,
+                    weight: rule.weight as u64,
+                    negate: rule.negate,
+                    chord: rule.chord,
+                    list,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let points = layout.keys().iter().map(|v| v.point()).collect::<Vec<_>>();
+        let arpeggios = points
+            .iter()
+            .flat_map(|first| points.iter().map(move |second| (*first, *second)))
+            .filter(|(first, second)| layout.is_arpeggio(first, second))
+            .collect();
+
+        Ok(RuleSet {
+            rules,
+            arpeggios,
+            lists,
+        })
+    }
+
+    /// 2キーについて、`rule` の条件が成り立つかどうかを返す
+    fn holds(&self, rule: &Rule, profile: &HandProfile, me: &Point, other: &Point) -> bool {
+        let (me_key, other_key) = (profile.assignment(me), profile.assignment(other));
+        let same_hand = me_key.hand == other_key.hand;
+        let same_finger = same_hand && me_key.finger == other_key.finger;
+        let row_distance = me.row().abs_diff(other.row());
+
+        match rule.predicate {
+            Predicate::SameHand => same_hand,
+            Predicate::AlternateHand => !same_hand,
+            Predicate::SameFinger => same_finger,
+            Predicate::SameFingerSkipRow => same_finger && row_distance == 2,
+            Predicate::SameFingerDance => {
+                same_finger && me.row() != other.row() && me.col() != other.col()
+            }
+            Predicate::SkipRow => same_hand && !same_finger && row_distance == 2,
+            Predicate::Arpeggio => same_hand && self.arpeggios.contains(&(*me, *other)),
+            Predicate::InwardRoll => {
+                same_hand && finger_rank(me_key.finger) > finger_rank(other_key.finger)
+            }
+            Predicate::OutwardRoll => {
+                same_hand && finger_rank(me_key.finger) < finger_rank(other_key.finger)
+            }
+            Predicate::InList => rule
+                .list
+                .is_some_and(|idx| self.lists[idx].contains(&(*me, *other))),
+            Predicate::Pinky => me_key.finger == Finger::Pinky && other_key.finger == Finger::Pinky,
+        }
+    }
+
+    /// 連続する `strokes` に対して、同じ数のキーに対するルールを適用したペナルティを返す
+    ///
+    /// シフトキーの打鍵とその次の打鍵の組は同時押しとして、`chord` のルールのみを適用する。
+    /// ペナルティは、条件の種類に応じた内訳に加算する
+    pub fn evaluate(&self, profile: &HandProfile, strokes: &[Stroke]) -> ScoreBreakdown {
+        let mut breakdown = ScoreBreakdown::default();
+
+        for rule in self.rules.iter().filter(|v| v.keys == strokes.len()) {
+            let applied = strokes.windows(2).all(|pair| {
+                pair[0].shifter == rule.chord
+                    && self.holds(rule, profile, &pair[0].point, &pair[1].point) != rule.negate
+            });
+            if !applied {
+                continue;
+            }
+
+            let component = match rule.predicate {
+                _ if rule.chord => &mut breakdown.shift,
+                Predicate::SameFinger
+                | Predicate::SameFingerSkipRow
+                | Predicate::SameFingerDance => &mut breakdown.same_finger,
+                Predicate::SkipRow => &mut breakdown.skip_row,
+                Predicate::Arpeggio if rule.negate => &mut breakdown.non_arpeggio,
+                Predicate::Pinky => &mut breakdown.pinky_run,
+                _ => &mut breakdown.other_rules,
+            };
+            *component += rule.weight;
+        }
+
+        breakdown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_list_and_roll() {
+        // arrange
+        let layout = Layout::default();
+        let profile = HandProfile::standard(&layout);
+        let text = r#"
+            [[rules]]
+            keys = 2
+            predicate = "in-list"
+            list = "hard"
+            weight = 30
+
+            [[rules]]
+            keys = 3
+            predicate = "inward-roll"
+            weight = 10
+
+            [[rules]]
+            keys = 2
+            predicate = "arpeggio"
+            weight = 5
+
+            [[rules]]
+            keys = 2
+            predicate = "arpeggio"
+            negate = true
+            weight = 7
+
+            [lists]
+            hard = [["a", "z"]]
+        "#;
+        let point_of = |label| layout.key_of_label(label).unwrap().point();
+
+        // act
+        let rules = RuleSet::parse(text, &layout).unwrap();
+        let tap = |label| Stroke::tap(point_of(label));
+        let hard = rules.evaluate(&profile, &[tap('z'), tap('a')]);
+        let roll = rules.evaluate(&profile, &[tap('a'), tap('s'), tap('d')]);
+        let not_roll = rules.evaluate(&profile, &[tap('a'), tap('s'), tap('a')]);
+        let chord = rules.evaluate(&profile, &[Stroke::shifter(point_of('z')), tap('a')]);
+        let arpeggio = rules.evaluate(&profile, &[tap('e'), tap('f')]);
+        let non_arpeggio = rules.evaluate(&profile, &[tap('e'), tap('r')]);
+
+        // assert
+        assert_eq!(hard.other_rules, 30);
+        assert_eq!(hard.non_arpeggio, 7);
+        assert_eq!(roll.other_rules, 10);
+        assert_eq!(
+            arpeggio.other_rules, 5,
+            "arpeggio itself is not non-arpeggio"
+        );
+        assert_eq!(arpeggio.non_arpeggio, 0);
+        assert_eq!(non_arpeggio.non_arpeggio, 7);
+        assert_eq!(not_roll.total(), 0);
+        assert_eq!(chord.total(), 0, "should not apply to chord");
+    }
+
+    #[test]
+    fn reject_undefined_list() {
+        // arrange
+        let layout = Layout::default();
+        let text = "[[rules]]\nkeys = 2\npredicate = \"in-list\"\nlist = \"none\"\nweight = 1";
+
+        // act
+        let result = RuleSet::parse(text, &layout);
+
+        // assert
+        assert!(result.is_err());
+    }
+}
@@ -4,17 +4,14 @@ use std::{
 };
 
 use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    connection_score::ConnectionScore,
     frequency_layer::LayeredCharCombination,
     frequency_table::{FrequencyTable, KeyAssigner},
     keymap::Keymap,
-    layout::linear::{
-        LINEAR_L_SEMITURBID_INDEX, LINEAR_L_TURBID_INDEX, LINEAR_R_SEMITURBID_INDEX,
-        LINEAR_R_TURBID_INDEX,
-    },
-    score::{self, Conjunction, Score},
+    layout::linear::{l_semiturbid_index, l_turbid_index, r_semiturbid_index, r_turbid_index},
+    score::{Corpus, Score},
 };
 
 /// 遺伝的アルゴリズムを実行するための基盤を生成する
@@ -22,15 +19,57 @@ use crate::{
 pub struct Playground {
     generation: u64,
     keymaps: Vec<Keymap>,
+    settings: Settings,
 
     frequency_table: FrequencyTable,
     pool: threadpool::ThreadPool,
+
+    /// 評価に利用するデータ。各workerと共有する
+    corpus: Arc<Corpus>,
+}
+
+/// [Playground]を再開するために必要な状態
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaygroundState {
+    generation: u64,
+    keymaps: Vec<Keymap>,
+    frequency_table: FrequencyTable,
+}
+
+impl PlaygroundState {
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
 }
 
 const TOURNAMENT_SIZE: usize = 3;
 const KEYMAP_SIZE: usize = 10;
-const WORKERS: u8 = 24;
-const MUTATION_PROB: f64 = 0.0001;
+const WORKERS: usize = 24;
+
+/// [Playground]の動作を決める設定
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// 頻度表の更新に利用するkeymapの数
+    pub tournament_size: usize,
+    /// 1世代あたりのkeymapの数
+    pub keymap_size: usize,
+    /// 評価に利用するthreadの数
+    pub workers: usize,
+    /// 頻度表に突然変異を与える確率。0の場合は突然変異を行わない
+    pub mutation_prob: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            tournament_size: TOURNAMENT_SIZE,
+            keymap_size: KEYMAP_SIZE,
+            workers: WORKERS,
+            mutation_prob: 0.0,
+        }
+    }
+}
 
 /// キー毎に設定する制約条件を生成する
 fn get_predicates(rng: &mut StdRng) -> HashMap<usize, Vec<fn(&LayeredCharCombination) -> bool>> {
@@ -38,7 +77,7 @@ fn get_predicates(rng: &mut StdRng) -> HashMap<usize, Vec<fn(&LayeredCharCombina
 
     if rng.gen::<bool>() {
         ret.insert(
-            LINEAR_L_TURBID_INDEX,
+            l_turbid_index(),
             vec![
                 |v: &LayeredCharCombination| {
                     v.char_of_layer("normal")
@@ -51,7 +90,7 @@ fn get_predicates(rng: &mut StdRng) -> HashMap<usize, Vec<fn(&LayeredCharCombina
             ],
         );
         ret.insert(
-            LINEAR_L_SEMITURBID_INDEX,
+            l_semiturbid_index(),
             vec![
                 |v: &LayeredCharCombination| {
                     v.char_of_layer("normal")
@@ -65,7 +104,7 @@ fn get_predicates(rng: &mut StdRng) -> HashMap<usize, Vec<fn(&LayeredCharCombina
         );
     } else {
         ret.insert(
-            LINEAR_R_TURBID_INDEX,
+            r_turbid_index(),
             vec![
                 |v: &LayeredCharCombination| {
                     v.char_of_layer("normal")
@@ -78,7 +117,7 @@ fn get_predicates(rng: &mut StdRng) -> HashMap<usize, Vec<fn(&LayeredCharCombina
             ],
         );
         ret.insert(
-            LINEAR_R_SEMITURBID_INDEX,
+            r_semiturbid_index(),
             vec![|v: &LayeredCharCombination| {
                 v.char_of_layer("normal")
                     .map_or(true, |v| v.is_cleartone() && !v.is_sulphuric())
@@ -92,12 +131,21 @@ fn get_predicates(rng: &mut StdRng) -> HashMap<usize, Vec<fn(&LayeredCharCombina
 }
 
 impl Playground {
-    pub fn new(gen_count: u8, rng: &mut StdRng, frequency_table: FrequencyTable) -> Self {
-        assert!(gen_count > 0, "gen_count must be greater than 0");
+    pub fn new(
+        settings: &Settings,
+        rng: &mut StdRng,
+        frequency_table: FrequencyTable,
+        corpus: Arc<Corpus>,
+    ) -> Self {
+        assert!(
+            settings.keymap_size > 0,
+            "keymap_size must be greater than 0"
+        );
+        assert!(settings.workers > 0, "workers must be greater than 0");
 
         // まずは必要な数だけ生成しておく
         let mut keymaps = Vec::new();
-        while keymaps.len() < KEYMAP_SIZE {
+        while keymaps.len() < settings.keymap_size {
             let mut assigner = KeyAssigner::from_freq(&frequency_table, &get_predicates(rng));
             if let Some(keymap) = Keymap::generate(rng, &mut assigner) {
                 keymaps.push(keymap);
@@ -105,10 +153,35 @@ impl Playground {
         }
 
         Playground {
-            pool: threadpool::ThreadPool::new(WORKERS as usize),
+            pool: threadpool::ThreadPool::new(settings.workers),
             generation: 1,
             keymaps,
+            settings: settings.clone(),
             frequency_table,
+            corpus,
+        }
+    }
+
+    /// 保存した状態から[Playground]を再開する
+    pub fn restore(settings: &Settings, state: PlaygroundState, corpus: Arc<Corpus>) -> Self {
+        assert!(settings.workers > 0, "workers must be greater than 0");
+
+        Playground {
+            pool: threadpool::ThreadPool::new(settings.workers),
+            generation: state.generation,
+            keymaps: state.keymaps,
+            settings: settings.clone(),
+            frequency_table: state.frequency_table,
+            corpus,
+        }
+    }
+
+    /// 再開するために必要な状態を返す
+    pub fn snapshot(&self) -> PlaygroundState {
+        PlaygroundState {
+            generation: self.generation,
+            keymaps: self.keymaps.clone(),
+            frequency_table: self.frequency_table.clone(),
         }
     }
 
@@ -124,46 +197,31 @@ impl Playground {
     ///
     /// 内部実装としては、分布表の更新と生成が主になるので、PBILと同類の動きである
     /// 結果として、今回の中でbestなscoreとkeymapを返す
-    pub fn advance(
-        &mut self,
-        rng: &mut StdRng,
-        conjunctions: &[Conjunction],
-        connection_score: Arc<ConnectionScore>,
-        do_neighbor_search: bool,
-    ) -> (u64, Keymap) {
+    pub fn advance(&mut self, rng: &mut StdRng, do_neighbor_search: bool) -> (u64, Keymap) {
         self.generation += 1;
 
         if do_neighbor_search {
-            return self.advance_with_neighbor(rng, conjunctions, connection_score);
+            return self.advance_with_neighbor();
         }
 
-        self.advance_with_ga(rng, conjunctions, connection_score)
+        self.advance_with_ga(rng)
     }
 
-    fn advance_with_neighbor(
-        &mut self,
-        rng: &mut StdRng,
-        conjunctions: &[Conjunction],
-        connection_score: Arc<ConnectionScore>,
-    ) -> (u64, Keymap) {
-        let rank = self.rank(&conjunctions, connection_score.clone()).to_vec();
+    fn advance_with_neighbor(&mut self) -> (u64, Keymap) {
+        let rank = self.rank().to_vec();
         let mut best_keymap = self.keymaps[rank[0].1].clone();
         let mut best_score = rank[0].0.clone();
         let mut search_count = 0;
 
         log::info!("Do neighbor search, current best score: {}", best_score);
         loop {
-            let (ranks, neighbors) = self.re_rank_neighbor(
-                conjunctions,
-                &best_score,
-                connection_score.clone(),
-                &best_keymap,
-            );
-            let best = neighbors[ranks[0].1].clone();
-            let score = ranks[0].0.clone();
-
-            if score < best_score {
-                best_score = score;
+            let (ranks, neighbors) = self.re_rank_neighbor(&best_score, &best_keymap);
+            let (score, idx) = ranks[0];
+
+            if score < u64::from(best_score.clone()) {
+                let best = neighbors[idx].clone();
+                let diff = best_keymap.diff(&best).into_iter().collect::<Vec<_>>();
+                best_score = self.corpus.evaluate_only_diff(&best_score, &best, &diff);
                 best_keymap = best;
             } else {
                 break;
@@ -178,24 +236,25 @@ impl Playground {
         (best_score.into(), best_keymap)
     }
 
-    fn advance_with_ga(
-        &mut self,
-        rng: &mut StdRng,
-        conjunctions: &[Conjunction],
-        connection_score: Arc<ConnectionScore>,
-    ) -> (u64, Keymap) {
-        let rank = self.rank(conjunctions, connection_score.clone()).to_vec();
+    fn advance_with_ga(&mut self, rng: &mut StdRng) -> (u64, Keymap) {
+        let rank = self.rank().to_vec();
         // self.keymapsを個体と見立てて、確率分布を更新する
-        for (rank, idx) in self.take_ranks(rng, &rank, TOURNAMENT_SIZE).iter() {
+        for (rank, idx) in self
+            .take_ranks(rng, &rank, self.settings.tournament_size)
+            .iter()
+        {
             self.frequency_table
                 .update(&self.keymaps[*idx], 1.0 / (*rank + 100) as f64);
         }
-        // self.frequency_table.mutate(rng, MUTATION_PROB);
+        if self.settings.mutation_prob > 0.0 {
+            self.frequency_table
+                .mutate(rng, self.settings.mutation_prob);
+        }
 
         let (tx, tr) = channel();
 
         let table = Arc::new(Box::new(self.frequency_table.clone()));
-        (0..KEYMAP_SIZE).for_each(|_| {
+        (0..self.settings.keymap_size).for_each(|idx| {
             let tx = tx.clone();
             let frequency_table = table.clone();
             let mut rng = StdRng::seed_from_u64(rng.gen());
@@ -204,32 +263,36 @@ impl Playground {
                 let mut assigner =
                     KeyAssigner::from_freq(&frequency_table, &get_predicates(&mut rng));
                 if let Some(new_keymap) = Keymap::generate(&mut rng, &mut assigner) {
-                    tx.send(new_keymap).unwrap();
+                    tx.send((idx, new_keymap)).unwrap();
                     break;
                 }
             })
         });
 
-        let new_keymaps: Vec<Keymap> = tr.iter().take(KEYMAP_SIZE).collect();
+        // 完了順に依存しないよう、生成を依頼した順序に並べ直す
+        let mut new_keymaps: Vec<(usize, Keymap)> =
+            tr.iter().take(self.settings.keymap_size).collect();
+        new_keymaps.sort_by_key(|(idx, _)| *idx);
+        let new_keymaps = new_keymaps.into_iter().map(|(_, k)| k).collect();
         let best_keymap = self.keymaps[rank[0].1].clone();
         self.keymaps = new_keymaps;
         (rank[0].0.clone().into(), best_keymap)
     }
 
     /// 最近傍探索をして、類似keymapのなかでbestなものを探す
+    ///
+    /// 近傍は `keymap` との差分となる文字を含む連接のみを再評価する。返却するscoreは合計値のみである。
+    /// 近傍はworkerの数に分割し、分割した単位で評価する
     fn re_rank_neighbor(
         &self,
-        conjunctions: &[Conjunction],
         score: &Score,
-        connection_score: Arc<ConnectionScore>,
         keymap: &Keymap,
-    ) -> (Vec<(Score, usize)>, Vec<Keymap>) {
-        let conjunctions = Arc::new(conjunctions.to_vec());
+    ) -> (Vec<(u64, usize)>, Arc<Vec<Keymap>>) {
         let score = Arc::new(score.clone());
         let keymap = Arc::new(keymap.clone());
         let (tx, tr) = channel();
         let mut keymaps: Vec<Keymap> = Vec::with_capacity(5000);
-        let len = keymap.iter().collect::<Vec<_>>().len();
+        let len = keymap.iter().count();
 
         for i in 0..len {
             for j in (i + 1)..len {
@@ -238,24 +301,36 @@ impl Playground {
             }
         }
 
-        keymaps.iter().enumerate().for_each(|(idx, k)| {
-            let conjunctions = conjunctions.clone();
+        let keymaps = Arc::new(keymaps);
+        let batch_size = keymaps.len().div_ceil(self.settings.workers).max(1);
+        let batches = keymaps.len().div_ceil(batch_size);
+
+        for batch in 0..batches {
+            let corpus = self.corpus.clone();
+            let keymaps = keymaps.clone();
             let keymap = keymap.clone();
-            let k = k.clone();
-            let tx = tx.clone();
             let score = score.clone();
-            let pre_scores = connection_score.clone();
+            let tx = tx.clone();
 
             self.pool.execute(move || {
-                // let diff = keymap.diff(&k).into_iter().collect::<Vec<_>>();
-                // let score = score.evaluate_only_diff(&conjunctions, &pre_scores, &k, &diff);
-                let score = score::evaluate(&conjunctions, &pre_scores, &k);
-                tx.send((score, idx)).expect("should be success")
+                let start = batch * batch_size;
+                let end = (start + batch_size).min(keymaps.len());
+                let mut cache = corpus.pos_cache(&keymap);
+                let scores = (start..end)
+                    .map(|idx| {
+                        let diff = keymap.diff(&keymaps[idx]).into_iter().collect::<Vec<_>>();
+                        (
+                            corpus.total_only_diff(&score, &mut cache, &keymaps[idx], &diff),
+                            idx,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                tx.send(scores).expect("should be success")
             })
-        });
+        }
 
-        let mut scores: Vec<(Score, usize)> = tr.iter().take(keymaps.len()).collect();
-        scores.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut scores: Vec<(u64, usize)> = tr.iter().take(batches).flatten().collect();
+        scores.sort();
         (scores, keymaps)
     }
 
@@ -293,29 +368,109 @@ impl Playground {
     /// scoreに基づいてkeymapをランク付けする。
     ///
     /// この中から、全体の特定の%までに対して確率を按分する
-    fn rank(
-        &self,
-        conjunctions: &[Conjunction],
-        connection_score: Arc<ConnectionScore>,
-    ) -> Vec<(Score, usize)> {
-        let conjunctions = Arc::new(conjunctions.to_vec());
-
+    fn rank(&self) -> Vec<(Score, usize)> {
         let keymaps = self.keymaps.clone();
         let (tx, tr) = channel();
 
         keymaps.into_iter().enumerate().for_each(|(idx, k)| {
             let tx = tx.clone();
-            let conjunctions = conjunctions.clone();
-            let pre_scores = connection_score.clone();
+            let corpus = self.corpus.clone();
 
             self.pool.execute(move || {
-                let score = score::evaluate(&conjunctions, &pre_scores, &k);
+                let score = corpus.evaluate(&k);
                 tx.send((score, idx)).expect("should be success")
             })
         });
 
         let mut scores: Vec<(Score, usize)> = tr.iter().take(self.keymaps.len()).collect();
-        scores.sort_by(|a, b| a.0.cmp(&b.0));
+        scores.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
         scores
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        char_def, connection_score::ConnectionScore, hand_profile::HandProfile, layout,
+        penalty::RuleSet, score::Conjunction, timing::TwoKeyTiming,
+    };
+
+    use super::*;
+
+    fn conjunctions() -> Vec<Conjunction> {
+        let chars = char_def::all_chars();
+
+        chars
+            .windows(4)
+            .enumerate()
+            .map(|(idx, _)| Conjunction::new((idx..idx + 4).collect(), 1, &chars))
+            .collect()
+    }
+
+    fn corpus() -> Arc<Corpus> {
+        Arc::new(Corpus::new(
+            conjunctions(),
+            ConnectionScore::new(
+                &TwoKeyTiming {
+                    timings: HashMap::new(),
+                },
+                &HandProfile::standard(layout::current()),
+                &RuleSet::builtin(layout::current()),
+            ),
+        ))
+    }
+
+    #[test]
+    fn same_seed_makes_same_result() {
+        // arrange
+        let settings = Settings {
+            workers: 4,
+            ..Default::default()
+        };
+        let corpus = corpus();
+        let run = || {
+            let mut rng = StdRng::seed_from_u64(42);
+            let mut playground =
+                Playground::new(&settings, &mut rng, FrequencyTable::new(), corpus.clone());
+
+            [false, false, true]
+                .into_iter()
+                .map(|neighbor| playground.advance(&mut rng, neighbor))
+                .collect::<Vec<_>>()
+        };
+
+        // act
+        let first = run();
+        let second = run();
+
+        // assert
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn continue_from_restored_state() {
+        // arrange
+        let settings = Settings {
+            workers: 4,
+            ..Default::default()
+        };
+        let corpus = corpus();
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut playground =
+            Playground::new(&settings, &mut rng, FrequencyTable::new(), corpus.clone());
+        playground.advance(&mut rng, false);
+        let bin = postcard::to_allocvec(&playground.snapshot()).unwrap();
+        let state: PlaygroundState = postcard::from_bytes(&bin).unwrap();
+        let mut restored = Playground::restore(&settings, state, corpus);
+
+        // act
+        let expected = playground.advance(&mut StdRng::seed_from_u64(1), false);
+        let actual = restored.advance(&mut StdRng::seed_from_u64(1), false);
+
+        // assert
+        assert_eq!(actual, expected);
+        assert_eq!(restored.snapshot(), playground.snapshot());
+    }
+}
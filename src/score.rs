@@ -1,8 +1,9 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 
 use crate::{
     char_def,
-    connection_score::{ConnectionScore, Evaluation},
+    checkpoint::Fingerprint,
+    connection_score::{ConnectionScore, KeyStrokes, ScoreBreakdown},
     keymap::Keymap,
 };
 
@@ -15,11 +16,31 @@ pub struct Conjunction {
     /// 連接の出現回数
     pub appearances: u32,
 
-    /// 各文字に対応する素数を乗算したもの
+    /// 各文字に対応する素数を乗算したもの。桁あふれする長さの場合は0とする
     pub hash: u64,
 }
 
 impl Conjunction {
+    /// 連接を作成する。長さは任意である
+    ///
+    /// # Arguments
+    /// * `text` - all_charsにおけるindexの列
+    /// * `appearances` - 連接の出現回数
+    /// * `all_chars` - [char_def::all_chars]の結果。大量の連接を作成するため、呼び出し側で一度だけ取得する
+    pub fn new(text: Vec<usize>, appearances: u32, all_chars: &[(u64, char)]) -> Self {
+        // 0はすべての素数で割り切れるため、桁あふれした連接は常に再評価の対象になる
+        let hash = text
+            .iter()
+            .try_fold(1u64, |accum, v| accum.checked_mul(all_chars[*v].0))
+            .unwrap_or(0);
+
+        Conjunction {
+            text,
+            appearances,
+            hash,
+        }
+    }
+
     /// 指定された文字を含まず、再評価が必要ないかを判定する
     ///
     /// # Arguments
@@ -36,6 +57,144 @@ impl Conjunction {
     }
 }
 
+/// 評価に利用する、変更されないデータ一式。
+///
+/// 連接は巨大になりうるため、一度だけ読み込んで[std::sync::Arc]で共有することを前提にしている
+pub struct Corpus {
+    conjunctions: Vec<Conjunction>,
+    connection_score: ConnectionScore,
+    /// 各文字と、all_charsにおけるindexと素数の組
+    char_indices: HashMap<char, (usize, u64)>,
+}
+
+impl std::fmt::Debug for Corpus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Corpus")
+            .field("conjunctions", &self.conjunctions.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Corpus {
+    pub fn new(conjunctions: Vec<Conjunction>, connection_score: ConnectionScore) -> Self {
+        let char_indices = char_def::all_chars()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (prime, c))| (c, (idx, prime)))
+            .collect();
+
+        Corpus {
+            conjunctions,
+            connection_score,
+            char_indices,
+        }
+    }
+
+    /// 連接の一覧のfingerprintを返す
+    pub fn conjunctions_fingerprint(&self) -> u64 {
+        let mut fingerprint = Fingerprint::new();
+        for conjunction in self.conjunctions.iter() {
+            fingerprint.write(&(conjunction.text.len() as u64).to_le_bytes());
+            for v in conjunction.text.iter() {
+                fingerprint.write(&(*v as u64).to_le_bytes());
+            }
+            fingerprint.write(&conjunction.appearances.to_le_bytes());
+        }
+        fingerprint.finish()
+    }
+
+    /// 打鍵ごとの評価の表のfingerprintを返す
+    pub fn connection_score_fingerprint(&self) -> u64 {
+        let mut fingerprint = Fingerprint::new();
+        self.connection_score.fingerprint(&mut fingerprint);
+        fingerprint.finish()
+    }
+
+    /// [keymap]の評価を行う
+    pub fn evaluate(&self, keymap: &Keymap) -> Score {
+        evaluate(&self.conjunctions, &self.connection_score, keymap)
+    }
+
+    /// `keymap` の各文字を入力する打鍵を、[Corpus::total_only_diff]で使い回すcacheとして返す
+    pub fn pos_cache(&self, keymap: &Keymap) -> PosCache {
+        PosCache {
+            strokes: make_pos_cache(&self.connection_score, keymap),
+        }
+    }
+
+    /// `diff_chars` の各文字と、all_charsにおけるindex・素数を返す
+    fn lookup(&self, diff_chars: &[char]) -> Vec<(char, usize, u64)> {
+        diff_chars
+            .iter()
+            .filter_map(|c| {
+                self.char_indices
+                    .get(c)
+                    .map(|(idx, prime)| (*c, *idx, *prime))
+            })
+            .collect()
+    }
+
+    /// [Score::evaluate_only_diff]を行う
+    pub fn evaluate_only_diff(&self, score: &Score, keymap: &Keymap, diff_chars: &[char]) -> Score {
+        let primes = self
+            .lookup(diff_chars)
+            .iter()
+            .map(|(_, _, prime)| *prime)
+            .collect::<Vec<_>>();
+        let pos_cache = make_pos_cache(&self.connection_score, keymap);
+
+        score.evaluate_only_diff(
+            &self.conjunctions,
+            &self.connection_score,
+            &pos_cache,
+            &primes,
+        )
+    }
+
+    /// [Score::total_only_diff]を行う。
+    ///
+    /// `cache` は、 `score` を評価したキーマップから[Corpus::pos_cache]で作成したものとする。
+    /// 差分の文字だけを `keymap` のものに書き換えて評価し、終了時に元に戻すため、多数の近傍で使い回せる
+    pub fn total_only_diff(
+        &self,
+        score: &Score,
+        cache: &mut PosCache,
+        keymap: &Keymap,
+        diff_chars: &[char],
+    ) -> u64 {
+        let diff = self.lookup(diff_chars);
+        let saved = diff
+            .iter()
+            .map(|(_, idx, _)| cache.strokes[*idx])
+            .collect::<Vec<_>>();
+        for (c, idx, _) in diff.iter() {
+            let Some(seq) = keymap.get(*c) else {
+                unreachable!("should not have any missing key")
+            };
+            cache.strokes[*idx] = self.connection_score.key_strokes(&seq.strokes());
+        }
+
+        let primes = diff.iter().map(|(_, _, prime)| *prime).collect::<Vec<_>>();
+        let total = score.total_only_diff(
+            &self.conjunctions,
+            &self.connection_score,
+            &cache.strokes,
+            &primes,
+        );
+
+        for ((_, idx, _), strokes) in diff.iter().zip(saved) {
+            cache.strokes[*idx] = strokes;
+        }
+        total
+    }
+}
+
+/// all_charsの順序で、各文字を入力する打鍵を保持するcache
+#[derive(Debug, Clone)]
+pub struct PosCache {
+    strokes: Vec<KeyStrokes>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Evaluated {
     // conjunctionを評価した結果
@@ -44,13 +203,21 @@ pub struct Evaluated {
     conjunction_index: usize,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct Score {
     // conjunctionの評価結果
-    // evaluated: Vec<Evaluated>,
+    evaluated: Vec<Evaluated>,
     total_score: u64,
 }
 
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.total_score == other.total_score
+    }
+}
+
+impl Eq for Score {}
+
 impl Ord for Score {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         self.total_score.cmp(&other.total_score)
@@ -75,20 +242,16 @@ impl Display for Score {
     }
 }
 
-fn make_pos_cache(keymap: &Keymap) -> Vec<Evaluation> {
-    let mut pos_cache: Vec<Evaluation> = Vec::with_capacity(char_def::all_chars().len());
+/// all_charsの順序で、各文字を入力する打鍵を返す
+fn make_pos_cache(pre_scores: &ConnectionScore, keymap: &Keymap) -> Vec<KeyStrokes> {
+    let mut pos_cache: Vec<KeyStrokes> = Vec::with_capacity(char_def::all_chars().len());
 
     for (_, c) in char_def::all_chars().iter() {
         let Some(v) = keymap.get(*c) else {
             unreachable!("should not have any missing key")
         };
 
-        let (p, shift) = v.as_raw_sequence();
-
-        pos_cache.push(Evaluation {
-            positions: p,
-            shift: shift.is_some(),
-        });
+        pos_cache.push(pre_scores.key_strokes(&v.strokes()));
     }
 
     pos_cache
@@ -99,7 +262,8 @@ impl Score {
     ///
     /// # Arguments
     /// * `pre_scores` - 事前に評価した連接評価
-    /// * `diff_chars` - 前回との差分となる文字
+    /// * `pos_cache` - 評価するキーマップで、all_charsの順序に各文字を入力する打鍵
+    /// * `diff_primes` - 前回との差分となる文字の素数。[Keymap::diff]の結果を想定している
     ///
     /// # Returns
     /// 評価値
@@ -107,45 +271,92 @@ impl Score {
         &self,
         conjunctions: &[Conjunction],
         pre_scores: &ConnectionScore,
-        keymap: &Keymap,
-        diff_chars: &[char],
+        pos_cache: &[KeyStrokes],
+        diff_primes: &[u64],
     ) -> Score {
         let mut score_obj = self.clone();
-        let all_chars = char_def::all_chars();
-        let diff_chars = diff_chars
-            .iter()
-            .filter_map(|v| all_chars.iter().find(|(_, x)| *x == *v))
-            .map(|v| v.0)
-            .collect::<Vec<_>>();
 
-        let pos_cache = make_pos_cache(keymap);
+        self.for_each_diff(
+            conjunctions,
+            pre_scores,
+            pos_cache,
+            diff_primes,
+            |index, current_score| {
+                let evaluated = &mut score_obj.evaluated[index];
+                score_obj.total_score -= evaluated.score;
+                evaluated.score = current_score;
+                score_obj.total_score += current_score;
+            },
+        );
 
-        let mut key_sequence: [&Evaluation; 4] = [
-            &Evaluation::default(),
-            &Evaluation::default(),
-            &Evaluation::default(),
-            &Evaluation::default(),
-        ];
-        // for evaluated in score_obj.evaluated.iter_mut() {
-        //     let conj = unsafe { conjunctions.get_unchecked(evaluated.conjunction_index) };
+        score_obj
+    }
+
+    /// 変更があった文字に対する評価を行い、合計のみを返す。
+    ///
+    /// [Score::evaluate_only_diff]と同じ値になるが、連接ごとの評価を複製しないため、多数の近傍を評価する場合に利用する
+    pub fn total_only_diff(
+        &self,
+        conjunctions: &[Conjunction],
+        pre_scores: &ConnectionScore,
+        pos_cache: &[KeyStrokes],
+        diff_primes: &[u64],
+    ) -> u64 {
+        let mut total_score = self.total_score;
+
+        self.for_each_diff(
+            conjunctions,
+            pre_scores,
+            pos_cache,
+            diff_primes,
+            |index, current_score| {
+                total_score = total_score - self.evaluated[index].score + current_score;
+            },
+        );
 
-        //     if conj.can_skip_evaluation(&diff_chars) {
-        //         continue;
-        //     }
+        total_score
+    }
 
-        // for (idx, ch) in conj.text.iter().enumerate() {
-        //     let seq = &pos_cache[*ch];
+    /// `diff_primes` の文字を含む連接を再評価し、[Score::evaluated]のindexと新しい評価値で `f` を呼び出す
+    fn for_each_diff<F>(
+        &self,
+        conjunctions: &[Conjunction],
+        pre_scores: &ConnectionScore,
+        pos_cache: &[KeyStrokes],
+        diff_primes: &[u64],
+        mut f: F,
+    ) where
+        F: FnMut(usize, u64),
+    {
+        let mut key_sequence: Vec<&KeyStrokes> = Vec::new();
+        for (index, evaluated) in self.evaluated.iter().enumerate() {
+            let conj = &conjunctions[evaluated.conjunction_index];
 
-        //     key_sequence[idx] = seq;
-        // }
+            if conj.can_skip_evaluation(diff_primes) {
+                continue;
+            }
 
-        //     let current_score = pre_scores.evaluate(&key_sequence) * conj.appearances as u64;
-        //     score_obj.total_score -= evaluated.score;
-        //     evaluated.score = current_score;
-        //     score_obj.total_score += current_score;
-        // }
+            key_sequence.clear();
+            key_sequence.extend(conj.text.iter().map(|ch| &pos_cache[*ch]));
 
-        score_obj
+            let current_score = pre_scores.evaluate(&key_sequence) * conj.appearances as u64;
+            f(index, current_score);
+        }
+    }
+}
+
+/// 各連接について、キーの列を組み立てて `f` を呼び出す
+fn for_each_sequence<F>(conjunctions: &[Conjunction], pos_cache: &[KeyStrokes], mut f: F)
+where
+    F: FnMut(usize, &Conjunction, &[&KeyStrokes]),
+{
+    // 連接の長さは任意であるため、bufferを使い回して連接ごとの確保を避ける
+    let mut key_sequence: Vec<&KeyStrokes> = Vec::new();
+    for (index, conjunction) in conjunctions.iter().enumerate() {
+        key_sequence.clear();
+        key_sequence.extend(conjunction.text.iter().map(|ch| &pos_cache[*ch]));
+
+        f(index, conjunction, &key_sequence);
     }
 }
 
@@ -164,27 +375,204 @@ pub fn evaluate(
 ) -> Score {
     let mut score = 0;
 
-    let pos_cache = make_pos_cache(keymap);
+    let pos_cache = make_pos_cache(pre_scores, keymap);
 
-    let mut score_obj = Score { total_score: 0 };
+    let mut score_obj = Score {
+        evaluated: Vec::with_capacity(conjunctions.len()),
+        total_score: 0,
+    };
 
-    let mut key_sequence: [&Evaluation; 4] = [
-        &Evaluation::default(),
-        &Evaluation::default(),
-        &Evaluation::default(),
-        &Evaluation::default(),
-    ];
-    for (index, conjunction) in conjunctions.iter().enumerate() {
-        for (idx, ch) in conjunction.text.iter().enumerate() {
-            let seq = &pos_cache[*ch];
+    for_each_sequence(
+        conjunctions,
+        &pos_cache,
+        |index, conjunction, key_sequence| {
+            let current_score = pre_scores.evaluate(key_sequence) * conjunction.appearances as u64;
+            score += current_score;
+            score_obj.evaluated.push(Evaluated {
+                score: current_score,
+                conjunction_index: index,
+            });
+        },
+    );
 
-            key_sequence[idx] = seq;
+    score_obj.total_score = score;
+    score_obj
+}
+
+/// 評価が悪い連接
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorstConjunction {
+    /// 連接のテキスト
+    pub text: String,
+    /// 出現回数を乗算した評価値
+    pub score: u64,
+    /// 評価値の内訳
+    pub breakdown: ScoreBreakdown,
+}
+
+/// [keymap]の評価の内訳
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// 出現回数を乗算した評価値の内訳。合計は[evaluate]の結果と一致する
+    pub breakdown: ScoreBreakdown,
+    /// 評価値が悪い順の連接
+    pub worst: Vec<WorstConjunction>,
+}
+
+/// [keymap]の評価の内訳を返す。
+///
+/// # Arguments
+/// * `worst` - 返却する、評価が悪い連接の数
+pub fn explain(
+    conjunctions: &[Conjunction],
+    pre_scores: &ConnectionScore,
+    keymap: &Keymap,
+    worst: usize,
+) -> Explanation {
+    let pos_cache = make_pos_cache(pre_scores, keymap);
+    let mut breakdown = ScoreBreakdown::default();
+    let mut evaluated: Vec<(u64, usize, ScoreBreakdown)> = Vec::with_capacity(conjunctions.len());
+
+    for_each_sequence(
+        conjunctions,
+        &pos_cache,
+        |index, conjunction, key_sequence| {
+            let current = pre_scores
+                .explain(key_sequence)
+                .times(conjunction.appearances as u64);
+            breakdown += current;
+            evaluated.push((current.total(), index, current));
+        },
+    );
+
+    evaluated.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    let all_chars = char_def::all_chars();
+    let worst = evaluated
+        .into_iter()
+        .take(worst)
+        .map(|(score, index, breakdown)| WorstConjunction {
+            text: conjunctions[index]
+                .text
+                .iter()
+                .map(|v| all_chars[*v].1)
+                .collect(),
+            score,
+            breakdown,
+        })
+        .collect();
+
+    Explanation { breakdown, worst }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        frequency_table::FrequencyTable, hand_profile::HandProfile, keymap::tests::generate_keymap,
+        layout, penalty::RuleSet, timing::TwoKeyTiming,
+    };
+
+    use super::*;
+
+    #[test]
+    fn evaluate_only_diff_is_same_as_full_evaluation() {
+        // arrange
+        let chars = char_def::all_chars();
+        let conjunctions = (2..=6)
+            .flat_map(|len| {
+                (0..=(chars.len() - len)).map(move |idx| (idx..idx + len).collect::<Vec<_>>())
+            })
+            .map(|text| {
+                let appearances = text.len() as u32;
+                Conjunction::new(text, appearances, &chars)
+            })
+            .collect::<Vec<_>>();
+        let pre_scores = ConnectionScore::new(
+            &TwoKeyTiming {
+                timings: HashMap::new(),
+            },
+            &HandProfile::standard(layout::current()),
+            &RuleSet::builtin(layout::current()),
+        );
+        let corpus = Corpus::new(conjunctions, pre_scores);
+        let keymap = generate_keymap(&mut StdRng::seed_from_u64(1), &FrequencyTable::new());
+        let score = corpus.evaluate(&keymap);
+        let len = keymap.iter().count();
+        let neighbors = (0..len)
+            .flat_map(|i| ((i + 1)..len).map(move |j| (i, j)))
+            .flat_map(|(i, j)| keymap.swap_keys(i, j))
+            .collect::<Vec<_>>();
+        // 近傍の間でcacheを使い回しても、結果が変わらないことを確認する
+        let mut cache = corpus.pos_cache(&keymap);
+
+        for neighbor in neighbors.iter() {
+            let diff = keymap.diff(neighbor).into_iter().collect::<Vec<_>>();
+
+            // act
+            let ret = corpus.evaluate_only_diff(&score, neighbor, &diff);
+            let total = corpus.total_only_diff(&score, &mut cache, neighbor, &diff);
+
+            // assert
+            let expected = corpus.evaluate(neighbor);
+            assert_eq!(ret.total_score, expected.total_score);
+            assert_eq!(ret.evaluated, expected.evaluated);
+            assert_eq!(total, expected.total_score);
         }
+    }
+
+    #[test]
+    fn long_conjunction_is_always_reevaluated() {
+        // arrange
+        let chars = char_def::all_chars();
+        let last = chars.len() - 1;
 
-        let current_score = pre_scores.evaluate(&key_sequence) * conjunction.appearances as u64;
-        score += current_score;
+        // act
+        let short = Conjunction::new(vec![0, 1], 1, &chars);
+        let long = Conjunction::new(vec![last; 12], 1, &chars);
+
+        // assert
+        assert_eq!(short.hash, chars[0].0 * chars[1].0);
+        assert!(short.can_skip_evaluation(&[chars[last].0]));
+        assert_eq!(long.hash, 0, "should not overflow");
+        assert!(!long.can_skip_evaluation(&[chars[0].0]));
     }
 
-    score_obj.total_score = score;
-    score_obj
+    #[test]
+    fn fingerprint_changes_with_hand_profile() {
+        // arrange
+        let corpus_of = |profile: &str| {
+            let connection_score = ConnectionScore::new(
+                &TwoKeyTiming {
+                    timings: HashMap::new(),
+                },
+                &HandProfile::load(profile, layout::current()).unwrap(),
+                &RuleSet::builtin(layout::current()),
+            );
+            let conjunction = Conjunction::new(vec![0, 1], 1, &char_def::all_chars());
+            Corpus::new(vec![conjunction], connection_score)
+        };
+
+        // act
+        let standard = corpus_of("standard");
+        let same = corpus_of("standard");
+        let avoid_pinky = corpus_of("avoid-pinky");
+
+        // assert
+        assert_eq!(
+            standard.conjunctions_fingerprint(),
+            avoid_pinky.conjunctions_fingerprint()
+        );
+        assert_eq!(
+            standard.connection_score_fingerprint(),
+            same.connection_score_fingerprint()
+        );
+        assert_ne!(
+            standard.connection_score_fingerprint(),
+            avoid_pinky.connection_score_fingerprint()
+        );
+    }
 }
@@ -0,0 +1,774 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use scraper::{Html, Selector};
+
+use crate::layout::{linear::linear_mapping, Point};
+
+/// 打鍵時間の表において、未計測を表す値
+const UNMEASURED: u32 = 0;
+
+/// 打鍵時間の表において、計測できなかったことを表す上限値
+const CAPPED: u32 = 1000;
+
+/// 打鍵時間ファイルのheader。`samples` は省略できる
+const HEADER: [&str; 4] = ["from", "to", "ms", "samples"];
+
+/// 打鍵時間の読み書きで発生するエラー
+#[derive(Debug)]
+pub enum TimingError {
+    /// ファイルの読み書きに失敗した
+    Io(io::Error),
+    /// CSV/TSVとして読み書きできなかった
+    Csv(csv::Error),
+    /// headerが `from`, `to`, `ms`, (`samples`) ではない
+    InvalidHeader(Vec<String>),
+    /// 行の内容が不正
+    InvalidRecord { line: u64, reason: String },
+    /// 同じキーの組み合わせが複数回記録されている
+    DuplicatedPair { line: u64, from: char, to: char },
+    /// HTMLに打鍵時間の表が存在しない
+    MissingMatrix,
+    /// HTMLの表のセルが不正
+    InvalidCell {
+        row: usize,
+        col: usize,
+        text: String,
+    },
+}
+
+impl Display for TimingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimingError::Io(e) => write!(f, "{}", e),
+            TimingError::Csv(e) => write!(f, "{}", e),
+            TimingError::InvalidHeader(header) => write!(
+                f,
+                "header should be `from, to, ms[, samples]`, but got `{}`",
+                header.join(", ")
+            ),
+            TimingError::InvalidRecord { line, reason } => write!(f, "line {}: {}", line, reason),
+            TimingError::DuplicatedPair { line, from, to } => {
+                write!(
+                    f,
+                    "line {}: timing of '{}'->'{}' is duplicated",
+                    line, from, to
+                )
+            }
+            TimingError::MissingMatrix => write!(f, "table `#matrix > tbody` is not found"),
+            TimingError::InvalidCell { row, col, text } => {
+                write!(
+                    f,
+                    "cell at row {}, col {}: invalid value '{}'",
+                    row, col, text
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TimingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TimingError::Io(e) => Some(e),
+            TimingError::Csv(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TimingError {
+    fn from(value: io::Error) -> Self {
+        TimingError::Io(value)
+    }
+}
+
+impl From<csv::Error> for TimingError {
+    fn from(value: csv::Error) -> Self {
+        TimingError::Csv(value)
+    }
+}
+
+/// 2キーの打鍵時間の1件分。キーはレイアウトのラベルで表す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingRecord {
+    pub from: char,
+    pub to: char,
+    /// 打鍵時間(ミリ秒)
+    pub millis: u32,
+    /// 計測した回数。不明な場合はNone
+    pub samples: Option<u32>,
+}
+
+/// ファイル名から区切り文字を決める。`.tsv` であればタブ、それ以外はカンマとする
+fn delimiter_of(path: &Path) -> u8 {
+    match path.extension().and_then(|v| v.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("tsv") => b'\t',
+        _ => b',',
+    }
+}
+
+/// HTMLファイルかどうかを返す
+fn is_html(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|v| v.to_str()),
+        Some(ext) if ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm")
+    )
+}
+
+/// 1文字のキーを読み込む
+fn parse_key(line: u64, column: &str, value: &str) -> Result<char, TimingError> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(TimingError::InvalidRecord {
+            line,
+            reason: format!("{} should be a single key, but got '{}'", column, value),
+        }),
+    }
+}
+
+/// CSV/TSV形式の打鍵時間を読み込む。
+///
+/// ```text
+/// # 行頭が#の行はコメント
+/// from,to,ms,samples
+/// a,s,120,15
+/// j,k,98,
+/// ```
+pub fn read_records<R: Read>(reader: R, delimiter: u8) -> Result<Vec<TimingRecord>, TimingError> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .comment(Some(b'#'))
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+
+    let header = rdr.headers()?;
+    if !(3..=4).contains(&header.len()) || header.iter().zip(HEADER).any(|(v, h)| v != h) {
+        return Err(TimingError::InvalidHeader(
+            header.iter().map(|v| v.to_string()).collect(),
+        ));
+    }
+
+    let mut records = Vec::new();
+    let mut pairs = HashSet::new();
+    for result in rdr.records() {
+        let record = result?;
+        let line = record.position().map_or(0, |v| v.line());
+        if !(3..=4).contains(&record.len()) {
+            return Err(TimingError::InvalidRecord {
+                line,
+                reason: format!("expected 3 or 4 fields, but got {}", record.len()),
+            });
+        }
+
+        let from = parse_key(line, "from", &record[0])?;
+        let to = parse_key(line, "to", &record[1])?;
+        let millis = record[2]
+            .parse::<u32>()
+            .map_err(|_| TimingError::InvalidRecord {
+                line,
+                reason: format!("ms should be milliseconds, but got '{}'", &record[2]),
+            })?;
+        let samples = match record.get(3) {
+            None | Some("") => None,
+            Some(v) => match v.parse::<u32>() {
+                Ok(v) if v > 0 => Some(v),
+                _ => {
+                    return Err(TimingError::InvalidRecord {
+                        line,
+                        reason: format!("samples should be a positive number, but got '{}'", v),
+                    })
+                }
+            },
+        };
+
+        if !pairs.insert((from, to)) {
+            return Err(TimingError::DuplicatedPair { line, from, to });
+        }
+        records.push(TimingRecord {
+            from,
+            to,
+            millis,
+            samples,
+        });
+    }
+
+    Ok(records)
+}
+
+/// 打鍵時間をCSV/TSV形式で書き出す
+pub fn write_records<W: Write>(
+    writer: W,
+    delimiter: u8,
+    records: &[TimingRecord],
+) -> Result<(), TimingError> {
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+
+    wtr.write_record(HEADER)?;
+    for record in records {
+        wtr.write_record([
+            record.from.to_string(),
+            record.to.to_string(),
+            record.millis.to_string(),
+            record.samples.map(|v| v.to_string()).unwrap_or_default(),
+        ])?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// HTMLから変換した打鍵時間と、変換しなかったセルの数
+#[derive(Debug, Default)]
+pub struct HtmlConversion {
+    pub records: Vec<TimingRecord>,
+    /// 未計測(0)だったセルの数
+    pub unmeasured: usize,
+    /// 上限値(1000)だったセルの数。`keep_capped` の場合は変換した上で数える
+    pub capped: usize,
+}
+
+/// `typing-time.html` の `#matrix > tbody` にある表から、打鍵時間を読み込む。
+///
+/// 表の1行目と各行の1列目はキーのラベルになっている。未計測である0は常に除外し、
+/// 上限値である1000は `keep_capped` の場合のみ残す
+pub fn read_html(text: &str, keep_capped: bool) -> Result<HtmlConversion, TimingError> {
+    let html = Html::parse_document(text);
+    let matrix_selector = Selector::parse("#matrix > tbody").unwrap();
+    let tr_selector = Selector::parse("tr").unwrap();
+    let td_selector = Selector::parse("td").unwrap();
+
+    let matrix = html
+        .select(&matrix_selector)
+        .next()
+        .ok_or(TimingError::MissingMatrix)?;
+    let rows = matrix
+        .select(&tr_selector)
+        .map(|row| {
+            row.select(&td_selector)
+                .map(|col| col.text().collect::<String>().trim().to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let Some((header, rows)) = rows.split_first() else {
+        return Err(TimingError::MissingMatrix);
+    };
+
+    let label = |row: usize, col: usize, text: &str| {
+        parse_key(0, "label", text).map_err(|_| TimingError::InvalidCell {
+            row,
+            col,
+            text: text.to_string(),
+        })
+    };
+    let labels = header
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(col, text)| label(0, col, text))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut conversion = HtmlConversion::default();
+    for (ridx, row) in rows.iter().enumerate() {
+        let ridx = ridx + 1;
+        let Some((from, cols)) = row.split_first() else {
+            continue;
+        };
+        let from = label(ridx, 0, from)?;
+
+        for (cidx, text) in cols.iter().enumerate() {
+            let invalid_cell = || TimingError::InvalidCell {
+                row: ridx,
+                col: cidx + 1,
+                text: text.to_string(),
+            };
+            let to = *labels.get(cidx).ok_or_else(invalid_cell)?;
+            let millis = text.parse::<u32>().map_err(|_| invalid_cell())?;
+
+            match millis {
+                UNMEASURED => {
+                    conversion.unmeasured += 1;
+                    continue;
+                }
+                CAPPED => {
+                    conversion.capped += 1;
+                    if !keep_capped {
+                        continue;
+                    }
+                }
+                _ => (),
+            }
+
+            conversion.records.push(TimingRecord {
+                from,
+                to,
+                millis,
+                samples: None,
+            });
+        }
+    }
+
+    Ok(conversion)
+}
+
+/// 打鍵時間のファイルを読み込む。
+///
+/// 拡張子が `.html` であれば `typing-time.html` 形式、それ以外はCSV/TSV形式として読み込む
+pub fn read_source(path: &Path) -> Result<Vec<TimingRecord>, TimingError> {
+    if is_html(path) {
+        let conversion = read_html(&fs::read_to_string(path)?, false)?;
+        log::info!(
+            "ignored {} unmeasured and {} capped timings in {}",
+            conversion.unmeasured,
+            conversion.capped,
+            path.display()
+        );
+        Ok(conversion.records)
+    } else {
+        read_records(File::open(path)?, delimiter_of(path))
+    }
+}
+
+/// 複数の打鍵時間を、キーの組み合わせごとに1つの値に集約する方法
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    /// 重み付き平均
+    Mean,
+    /// 重み付き中央値
+    Median,
+    /// 値の小さい側と大きい側から、それぞれ重みの合計に対して指定した割合の重みを除いた重み付き平均。
+    /// 境界にある値は、重みの一部だけを除く
+    TrimmedMean(f64),
+}
+
+impl Aggregation {
+    /// `values` を集約する。`values` は(打鍵時間, 重み)の組で、空ではないこと
+    fn apply(&self, values: &[(u32, f64)]) -> u32 {
+        let mut values = values.to_vec();
+        values.sort_by_key(|(v, _)| *v);
+
+        let values = match self {
+            Aggregation::Mean => values,
+            Aggregation::Median => {
+                let total = values.iter().map(|(_, w)| w).sum::<f64>();
+                let mut accum = 0.0;
+                for (v, w) in values.iter() {
+                    accum += w;
+                    if accum * 2.0 >= total {
+                        return *v;
+                    }
+                }
+                return values[values.len() - 1].0;
+            }
+            Aggregation::TrimmedMean(ratio) => {
+                // 重みを累積した区間のうち、[lower, upper]に含まれる部分の重みだけを残す
+                let total = values.iter().map(|(_, w)| w).sum::<f64>();
+                let (lower, upper) = (total * ratio, total * (1.0 - ratio));
+                let mut accum = 0.0;
+                values
+                    .iter()
+                    .filter_map(|(v, w)| {
+                        let (start, end) = (accum, accum + w);
+                        accum = end;
+                        let kept = end.min(upper) - start.max(lower);
+                        (kept > 0.0).then_some((*v, kept))
+                    })
+                    .collect()
+            }
+        };
+
+        let total = values.iter().map(|(_, w)| w).sum::<f64>();
+        let sum = values.iter().map(|(v, w)| *v as f64 * w).sum::<f64>();
+        (sum / total).round() as u32
+    }
+}
+
+/// ファイルごとの差が大きいとみなす、[Disagreement::spread]の既定の閾値
+pub const DISAGREEMENT_THRESHOLD: f64 = 0.5;
+
+/// 打鍵時間の元になったファイルごとの差が大きいキーの組み合わせ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disagreement {
+    pub from: char,
+    pub to: char,
+    /// 集約した打鍵時間
+    pub aggregated: u32,
+    /// 元になった打鍵時間。計測していないもの、重みが0のファイルのものはNone
+    pub values: Vec<Option<u32>>,
+}
+
+impl Disagreement {
+    /// 最大値と最小値の差の、集約した打鍵時間に対する比率
+    pub fn spread(&self) -> f64 {
+        let values = self.values.iter().flatten();
+        let max = values.clone().max().copied().unwrap_or(0);
+        let min = values.min().copied().unwrap_or(0);
+
+        (max - min) as f64 / self.aggregated.max(1) as f64
+    }
+}
+
+/// 複数の打鍵時間を集約した結果
+#[derive(Debug, Clone)]
+pub struct AggregatedTiming {
+    pub records: Vec<TimingRecord>,
+    /// 2つ以上のファイルで計測されたキーの組み合わせ。[Disagreement::spread]が大きい順に並ぶ
+    pub disagreements: Vec<Disagreement>,
+}
+
+/// 複数の打鍵時間を集約する。
+///
+/// `sources` は(打鍵時間, 重み)の組。あるファイルで計測されていないキーの組み合わせは、
+/// 計測しているファイルだけから集約するため、いずれかのファイルにあれば補完される。
+/// 結果はキーの組み合わせ順に並ぶ
+pub fn aggregate(
+    sources: &[(Vec<TimingRecord>, f64)],
+    aggregation: Aggregation,
+) -> AggregatedTiming {
+    let mut pairs: BTreeMap<(char, char), Vec<Option<&TimingRecord>>> = BTreeMap::new();
+    for (idx, (records, _)) in sources.iter().enumerate() {
+        for record in records {
+            pairs
+                .entry((record.from, record.to))
+                .or_insert_with(|| vec![None; sources.len()])[idx] = Some(record);
+        }
+    }
+
+    let mut records = Vec::new();
+    let mut disagreements = Vec::new();
+    for ((from, to), measured) in pairs {
+        let values = measured
+            .iter()
+            .zip(sources.iter())
+            .filter_map(|(record, (_, weight))| record.map(|v| (v.millis, *weight)))
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            continue;
+        }
+
+        let millis = aggregation.apply(&values);
+        let samples = measured
+            .iter()
+            .flatten()
+            .filter_map(|v| v.samples)
+            .reduce(|a, b| a + b);
+        records.push(TimingRecord {
+            from,
+            to,
+            millis,
+            samples,
+        });
+
+        if values.len() >= 2 {
+            disagreements.push(Disagreement {
+                from,
+                to,
+                aggregated: millis,
+                values: measured
+                    .iter()
+                    .zip(sources.iter())
+                    .map(|(record, (_, weight))| record.filter(|_| *weight > 0.0).map(|v| v.millis))
+                    .collect(),
+            });
+        }
+    }
+    disagreements.sort_by(|a, b| b.spread().total_cmp(&a.spread()));
+
+    AggregatedTiming {
+        records,
+        disagreements,
+    }
+}
+
+/// ２キーの連接における所要時間。
+#[derive(Debug, Clone)]
+pub struct TwoKeyTiming {
+    pub timings: HashMap<(Point, Point), u32>,
+}
+
+impl TwoKeyTiming {
+    /// 打鍵時間から、位置同士の打鍵時間を作成する。
+    ///
+    /// 文字を割り当てないキーやレイアウトに存在しないキーの打鍵時間は利用しない
+    pub fn from_records(records: &[TimingRecord]) -> TwoKeyTiming {
+        let mappings = linear_mapping();
+        let timings = records
+            .iter()
+            .filter_map(|record| {
+                let first = mappings.get(&record.from)?;
+                let second = mappings.get(&record.to)?;
+                Some(((*first, *second), record.millis))
+            })
+            .collect::<HashMap<_, _>>();
+
+        if timings.len() < records.len() {
+            log::info!(
+                "ignored {} timings of keys not assigned in the layout",
+                records.len() - timings.len()
+            );
+        }
+
+        TwoKeyTiming { timings }
+    }
+}
+
+/// `typing-time.html` 形式の打鍵時間を、CSV/TSV形式に変換する
+pub fn convert_html(
+    input: &Path,
+    output: Option<&Path>,
+    keep_capped: bool,
+) -> Result<HtmlConversion, TimingError> {
+    let conversion = read_html(&fs::read_to_string(input)?, keep_capped)?;
+    save_records(output, &conversion.records)?;
+
+    Ok(conversion)
+}
+
+/// 打鍵時間を `output` に書き出す。拡張子が `.tsv` であればTSV、それ以外はCSVとし、
+/// `output` がNoneの場合は標準出力にCSVで出力する。
+///
+/// 書き込み中に中断されても既存のファイルが壊れないよう、一時ファイルに書き込んでから置き換える
+pub fn save_records(output: Option<&Path>, records: &[TimingRecord]) -> Result<(), TimingError> {
+    let Some(path) = output else {
+        return write_records(io::stdout().lock(), b',', records);
+    };
+
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = File::create(&tmp)?;
+        write_records(&mut file, delimiter_of(path), records)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_csv_with_optional_samples() {
+        // arrange
+        let text = "# measured by hand\nfrom,to,ms,samples\na,s,120,15\nj,k,98,\n";
+
+        // act
+        let records = read_records(text.as_bytes(), b',').unwrap();
+
+        // assert
+        assert_eq!(
+            records,
+            vec![
+                TimingRecord {
+                    from: 'a',
+                    to: 's',
+                    millis: 120,
+                    samples: Some(15)
+                },
+                TimingRecord {
+                    from: 'j',
+                    to: 'k',
+                    millis: 98,
+                    samples: None
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn report_line_of_malformed_record() {
+        // arrange
+        let text = "from\tto\tms\na\ts\t120\nj\tk\tfast\n";
+
+        // act
+        let error = read_records(text.as_bytes(), b'\t').unwrap_err();
+
+        // assert
+        assert!(matches!(error, TimingError::InvalidRecord { line: 3, .. }));
+    }
+
+    #[test]
+    fn reject_duplicated_pair() {
+        // arrange
+        let text = "from,to,ms\na,s,120\na,s,130\n";
+
+        // act
+        let error = read_records(text.as_bytes(), b',').unwrap_err();
+
+        // assert
+        assert!(matches!(
+            error,
+            TimingError::DuplicatedPair {
+                line: 3,
+                from: 'a',
+                to: 's'
+            }
+        ));
+    }
+
+    #[test]
+    fn convert_html_to_records() {
+        // arrange
+        let html = r#"<table id="matrix"><tbody>
+            <tr><td></td><td>a</td><td>;</td></tr>
+            <tr><td>a</td><td>179</td><td>0</td></tr>
+            <tr><td>;</td><td>1000</td><td>145</td></tr>
+            </tbody></table>"#;
+
+        // act
+        let conversion = read_html(html, false).unwrap();
+        let mut buf = Vec::new();
+        write_records(&mut buf, b',', &conversion.records).unwrap();
+        let records = read_records(buf.as_slice(), b',').unwrap();
+
+        // assert
+        assert_eq!(conversion.unmeasured, 1);
+        assert_eq!(conversion.capped, 1);
+        assert_eq!(records, conversion.records);
+        assert_eq!(
+            records
+                .iter()
+                .map(|v| (v.from, v.to, v.millis))
+                .collect::<Vec<_>>(),
+            vec![('a', 'a', 179), (';', ';', 145)]
+        );
+    }
+
+    #[test]
+    fn reject_html_with_malformed_cell() {
+        // arrange
+        let html = r#"<table id="matrix"><tbody>
+            <tr><td></td><td>a</td></tr>
+            <tr><td>a</td><td>-</td></tr>
+            </tbody></table>"#;
+
+        // act
+        let error = read_html(html, false).unwrap_err();
+
+        // assert
+        assert!(matches!(
+            error,
+            TimingError::InvalidCell { row: 1, col: 1, .. }
+        ));
+    }
+
+    fn record(from: char, to: char, millis: u32) -> TimingRecord {
+        TimingRecord {
+            from,
+            to,
+            millis,
+            samples: None,
+        }
+    }
+
+    #[test]
+    fn aggregate_fills_missing_pairs() {
+        // arrange
+        let sources = vec![
+            (vec![record('a', 's', 100), record('j', 'k', 90)], 1.0),
+            (vec![record('a', 's', 200)], 3.0),
+        ];
+
+        // act
+        let aggregated = aggregate(&sources, Aggregation::Mean);
+
+        // assert
+        assert_eq!(
+            aggregated.records,
+            vec![record('a', 's', 175), record('j', 'k', 90)]
+        );
+        assert_eq!(
+            aggregated.disagreements,
+            vec![Disagreement {
+                from: 'a',
+                to: 's',
+                aggregated: 175,
+                values: vec![Some(100), Some(200)]
+            }]
+        );
+    }
+
+    #[test]
+    fn ignore_zero_weight_in_disagreements() {
+        // arrange
+        let sources = vec![
+            (vec![record('a', 's', 100)], 1.0),
+            (vec![record('a', 's', 110)], 1.0),
+            (vec![record('a', 's', 900)], 0.0),
+        ];
+
+        // act
+        let aggregated = aggregate(&sources, Aggregation::Mean);
+
+        // assert
+        assert_eq!(aggregated.records, vec![record('a', 's', 105)]);
+        assert_eq!(
+            aggregated.disagreements[0].values,
+            vec![Some(100), Some(110), None]
+        );
+        assert!(aggregated.disagreements[0].spread() < DISAGREEMENT_THRESHOLD);
+    }
+
+    #[test]
+    fn median_and_trimmed_mean_ignore_outlier() {
+        // arrange
+        let values = [(100, 1.0), (110, 1.0), (120, 1.0), (130, 1.0), (900, 1.0)];
+
+        // act
+        let median = Aggregation::Median.apply(&values);
+        let trimmed = Aggregation::TrimmedMean(0.2).apply(&values);
+        let mean = Aggregation::Mean.apply(&values);
+
+        // assert
+        assert_eq!(median, 120);
+        assert_eq!(trimmed, 120);
+        assert_eq!(mean, 272);
+    }
+
+    #[test]
+    fn trimmed_mean_trims_by_weight() {
+        // arrange
+        let values = [(100, 1.0), (110, 1.0), (120, 1.0), (130, 1.0), (200, 6.0)];
+
+        // act
+        let trimmed = Aggregation::TrimmedMean(0.2).apply(&values);
+
+        // assert
+        // 重みの合計10のうち、両端から2ずつ除くため、200は重み4だけ残る
+        assert_eq!(trimmed, 175);
+    }
+
+    #[test]
+    fn save_records_replaces_file() {
+        // arrange
+        let dir = std::env::temp_dir().join(format!("keymap-generator-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("timing.tsv");
+        let record = |to, millis| TimingRecord {
+            from: 'a',
+            to,
+            millis,
+            samples: None,
+        };
+        save_records(Some(&path), &[record('s', 100)]).unwrap();
+
+        // act
+        save_records(Some(&path), &[record('s', 100), record('d', 120)]).unwrap();
+
+        // assert
+        let records = read_records(File::open(&path).unwrap(), b'\t').unwrap();
+        assert_eq!(records, vec![record('s', 100), record('d', 120)]);
+        assert!(!path.with_extension("tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}